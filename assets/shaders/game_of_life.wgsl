@@ -1,5 +1,5 @@
 @group(0) @binding(0)
-var texture: texture_storage_2d<rgba8unorm, read_write>;
+var texture: texture_storage_2d<rgba16float, read_write>;
 
 @group(0) @binding(1)
 var texture_second: texture_storage_2d<rgba8unorm, read_write>;
@@ -7,31 +7,75 @@ var texture_second: texture_storage_2d<rgba8unorm, read_write>;
 struct Agent {
     position: vec2<f32>,
     angle: f32,
+    species: u32,
 };
 
 @group(0) @binding(2)
 var<storage, read_write> agents: array<Agent>;
 
 struct Params {
-    color: vec4<f32>,
     blur_mask: vec4<f32>,
     width: i32,
     height: i32,
-    mode: i32,
+    species_count: i32,
     trail_weight: f32,
     decay_rate: f32,
     time: f32,
     delta: f32,
     salt: u32,
+    blur_radius: u32,
+    blur_sigma: f32,
+    boundary_mode: u32,
+    food_weight: f32,
+    palette_enabled: u32,
+    brush_x: f32,
+    brush_y: f32,
+    brush_radius: f32,
+    brush_strength: f32,
+    brush_tool: u32,
+    brush_species: u32,
+};
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+struct Species {
+    color: vec4<f32>,
+    mode: i32,
     move_speed: f32,
     turn_speed: f32,
     sensor_angle_spacing: f32,
     sensor_offset_distance: f32,
     sensor_size: i32,
+    follow_mask_gradient: u32,
+    spawn_radius: f32,
+    spawn_count: u32,
+    spawn_heading: u32,
+    spawn_vertical: u32,
 };
 
-@group(0) @binding(3)
-var<uniform> params: Params;
+@group(0) @binding(4)
+var<storage, read> species: array<Species>;
+
+struct MaskTexel {
+    // Luminance of this and all previous pixels, normalized to end at 1
+    cdf: f32,
+    luminance: f32,
+};
+
+struct SpawnMask {
+    width: u32,
+    height: u32,
+    texels: array<MaskTexel>,
+};
+
+// The brushed copy of `texture`, agents deposit into it so none of them senses another's deposit
+// from the same step
+@group(0) @binding(5)
+var texture_out: texture_storage_2d<rgba16float, read_write>;
+
+@group(0) @binding(6)
+var<storage, read> spawn_mask: SpawnMask;
 
 let pi = 3.14159265359;
 
@@ -50,8 +94,89 @@ fn randomFloat(value: u32) -> f32 {
     return f32(hash(value)) / 4294967295.0;
 }
 
+// Boundary modes, see `BoundaryMode`
+let BOUNDARY_RANDOM_TURN = 0u;
+let BOUNDARY_WRAP = 1u;
+let BOUNDARY_REFLECT = 2u;
+let BOUNDARY_CLAMP = 3u;
+
+// The texel that is read in place of `location` when it lies outside the texture
+fn boundary_texel(location: vec2<i32>) -> vec2<i32> {
+    let size = vec2<i32>(params.width, params.height);
+    var texel = location;
+    if (params.boundary_mode == BOUNDARY_WRAP) {
+        texel = ((texel % size) + size) % size;
+    } else if (params.boundary_mode == BOUNDARY_REFLECT) {
+        texel = select(texel, -texel - 1, texel < vec2<i32>(0));
+        texel = select(texel, 2 * size - texel - 1, texel >= size);
+    }
+    return clamp(texel, vec2<i32>(0), size - 1);
+}
+
+// Index of the first mask pixel whose cumulative luminance reaches `value`
+fn sample_mask(value: f32) -> u32 {
+    var low = 0u;
+    var high = spawn_mask.width * spawn_mask.height - 1u;
+    loop {
+        if (low >= high) {
+            break;
+        }
+        let mid = (low + high) / 2u;
+        if (spawn_mask.texels[mid].cdf < value) {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+    return low;
+}
+
+fn mask_luminance(x: i32, y: i32) -> f32 {
+    let size = vec2<i32>(i32(spawn_mask.width), i32(spawn_mask.height));
+    let texel = clamp(vec2<i32>(x, y), vec2<i32>(0), size - 1);
+    return spawn_mask.texels[texel.y * size.x + texel.x].luminance;
+}
+
+// See `BrushTool`
+let BRUSH_REPEL = 3u;
+
+// Spawn headings, see `SpawnHeading`
+let HEADING_OUTWARD = 1u;
+let HEADING_INWARD = 2u;
+let HEADING_TANGENT = 3u;
+
+// Heading relative to the point the spawn shape is built around, random when on top of it
+fn spawn_heading(rule: u32, position: vec2<f32>, anchor: vec2<f32>, random: u32) -> f32 {
+    let away = position - anchor;
+    if (length(away) > 0.0) {
+        if (rule == HEADING_OUTWARD) {
+            return atan2(away.y, away.x);
+        } else if (rule == HEADING_INWARD) {
+            return atan2(-away.y, -away.x);
+        } else if (rule == HEADING_TANGENT) {
+            return atan2(away.x, -away.y);
+        }
+    }
+    return randomFloat(random) * 2.0 * pi;
+}
+
+// Each species deposits into its own channel of the trail, so neither the blur nor the species'
+// colors decide whose trail it is
+fn species_channel(index: u32) -> vec4<f32> {
+    return select(vec4<f32>(0.0), vec4<f32>(1.0), vec4<u32>(0u, 1u, 2u, 3u) == vec4<u32>(index));
+}
+
+// One in the channels of the species that are simulated
+fn active_channels() -> vec4<f32> {
+    return select(vec4<f32>(0.0), vec4<f32>(1.0), vec4<u32>(0u, 1u, 2u, 3u) < vec4<u32>(u32(params.species_count)));
+}
+
 @compute @workgroup_size(512, 1, 1)
 fn init(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&agents)) {
+        return;
+    }
+
     // let random = hash(id.x * params.width + id.x + hash(id.x + u32(params.time * 100000.123))) * u32(params.seed * params.delta);
     let random = hash(id.x * u32(params.width) * params.salt + u32(params.time * 100000.0));
 
@@ -59,14 +184,17 @@ fn init(@builtin(global_invocation_id) id: vec3<u32>) {
 
     let agent_id = id.x;
     let agent = &agents[agent_id];
+    let species_index = agent_id % u32(params.species_count);
+    let settings = species[species_index];
+    (*agent).species = species_index;
 
     // (*agent).position = vec2<f32>((randomFloat(id.x * random * u32((random + id.x) ^ 2123u) + id.x) * f32(params.width)), (randomFloat(id.x * u32(params.delta * params.seed) * random + id.x)  * f32(params.height)));
 
 
-    if (params.mode == 0) {
+    if (settings.mode == 0) {
         (*agent).position = center;
         (*agent).angle = randomFloat(random) * 2.0 * pi;
-    } else if (params.mode == 1) {
+    } else if (settings.mode == 1) {
         let theta = randomFloat(random) * 2.0 * pi;
         let seed = hash(random + params.salt);
         let r = f32(params.height) * 0.25 * sqrt(randomFloat(seed));
@@ -75,33 +203,106 @@ fn init(@builtin(global_invocation_id) id: vec3<u32>) {
         (*agent).position.y = center.y + r * cos(theta);
 
         (*agent).angle = theta + pi;
-    } else if (params.mode == 2) {
+    } else if (settings.mode == 2 || (settings.mode == 3 && spawn_mask.width == 0u)) {
         (*agent).position = vec2<f32>((randomFloat(id.x * random + id.x) * f32(params.width)), (randomFloat(id.x * u32(params.delta) * params.salt * random + id.x)  * f32(params.height)));
             
         (*agent).angle = randomFloat(random) * 2.0 * pi;
+    } else if (settings.mode == 3) {
+        let pixel = sample_mask(randomFloat(hash(random + 1u)));
+        let mask_x = i32(pixel % spawn_mask.width);
+        let mask_y = i32(pixel / spawn_mask.width);
+
+        // Fit the mask into the texture, keeping its aspect ratio
+        let size = vec2<f32>(f32(params.width), f32(params.height));
+        let mask_size = vec2<f32>(f32(spawn_mask.width), f32(spawn_mask.height));
+        let scale = min(size.x / mask_size.x, size.y / mask_size.y);
+        let offset = (size - mask_size * scale) / 2.0;
+        let jitter = vec2<f32>(randomFloat(hash(random + 2u)), randomFloat(hash(random + 3u)));
+        (*agent).position = min(offset + (vec2<f32>(f32(mask_x), f32(mask_y)) + jitter) * scale, size - vec2<f32>(0.001));
+
+        (*agent).angle = randomFloat(random) * 2.0 * pi;
+        if (settings.follow_mask_gradient != 0u) {
+            let gradient = vec2<f32>(
+                mask_luminance(mask_x + 1, mask_y) - mask_luminance(mask_x - 1, mask_y),
+                mask_luminance(mask_x, mask_y + 1) - mask_luminance(mask_x, mask_y - 1),
+            );
+            if (length(gradient) > 0.0) {
+                (*agent).angle = atan2(gradient.y, gradient.x);
+            }
+        }
+    } else if (settings.mode >= 4) {
+        // Shape sizes are relative to half the shorter side of the texture
+        let radius = settings.spawn_radius * min(center.x, center.y);
+        let count = max(settings.spawn_count, 1u);
+        let index = hash(random + 1u) % count;
+        let t = randomFloat(hash(random + 2u));
+        var anchor = center;
+
+        if (settings.mode == 4) {
+            // Concentric rings, evenly spaced out to `radius`
+            let ring_radius = radius * f32(index + 1u) / f32(count);
+            let theta = t * 2.0 * pi;
+            (*agent).position = center + ring_radius * vec2<f32>(cos(theta), sin(theta));
+        } else if (settings.mode == 5) {
+            // Archimedean spiral arms winding three times around the center
+            let theta = t * 3.0 * 2.0 * pi + f32(index) * 2.0 * pi / f32(count);
+            (*agent).position = center + t * radius * vec2<f32>(cos(theta), sin(theta));
+        } else if (settings.mode == 6) {
+            // Gaussian clusters with `radius` as the standard deviation
+            anchor = vec2<f32>(
+                randomFloat(hash(index * 2u + params.salt)),
+                randomFloat(hash(index * 2u + 1u + params.salt)),
+            ) * vec2<f32>(f32(params.width), f32(params.height));
+            let distance = radius * sqrt(-2.0 * log(max(randomFloat(hash(random + 3u)), 0.000001)));
+            let theta = t * 2.0 * pi;
+            (*agent).position = anchor + distance * vec2<f32>(cos(theta), sin(theta));
+        } else if (settings.mode == 7) {
+            // Line through the center, `radius` is relative to half of its own axis
+            let along = (t * 2.0 - 1.0) * settings.spawn_radius;
+            if (settings.spawn_vertical != 0u) {
+                (*agent).position = vec2<f32>(center.x, center.y + along * center.y);
+            } else {
+                (*agent).position = vec2<f32>(center.x + along * center.x, center.y);
+            }
+        } else if (settings.mode == 8) {
+            // Circle facing outward
+            let theta = t * 2.0 * pi;
+            (*agent).position = center + radius * vec2<f32>(cos(theta), sin(theta));
+        }
+
+        if (settings.mode == 8) {
+            (*agent).angle = spawn_heading(HEADING_OUTWARD, (*agent).position, anchor, random);
+        } else {
+            (*agent).angle = spawn_heading(settings.spawn_heading, (*agent).position, anchor, random);
+        }
+        (*agent).position = clamp((*agent).position, vec2<f32>(0.0), vec2<f32>(f32(params.width), f32(params.height)) - vec2<f32>(0.001));
     }
 
 
     let location = vec2<i32>(i32((*agent).position.x), i32((*agent).position.y));
-    textureStore(texture, location, vec4<f32>(params.color.xyz, 1.0));
+    textureStore(texture, location, max(textureLoad(texture, location), species_channel((*agent).species)));
 }
 
-fn sense(agent: Agent, sensor_angle_spacing: f32) -> f32 {
+fn sense(agent: Agent, settings: Species, sensor_angle_spacing: f32) -> f32 {
     let sensor_angle = agent.angle + sensor_angle_spacing;
     let sensorDir = vec2<f32>(cos(sensor_angle), sin(sensor_angle));
 
-	let sensorPos = agent.position + sensorDir * vec2<f32>(params.sensor_offset_distance);
+	let sensorPos = agent.position + sensorDir * vec2<f32>(settings.sensor_offset_distance);
 	let sensorCentreX = i32(sensorPos.x);
 	let sensorCentreY = i32(sensorPos.y);
 
+    let own = species_channel(agent.species);
+    let simulated = active_channels();
     var sum: f32;
 
-    for (var offsetX = params.sensor_size * -1; offsetX <= params.sensor_size; offsetX ++) {
-		for (var offsetY = params.sensor_size * -1; offsetY <= params.sensor_size; offsetY ++) {
-			let sampleX = min(params.width - 1, max(0, sensorCentreX + offsetX));
-			let sampleY = min(params.height - 1, max(0, sensorCentreY + offsetY));
-            let pixel_vec = textureLoad(texture, vec2<i32>(i32(sampleX),i32(sampleY)));
-			sum += pixel_vec.w;
+    for (var offsetX = settings.sensor_size * -1; offsetX <= settings.sensor_size; offsetX ++) {
+		for (var offsetY = settings.sensor_size * -1; offsetY <= settings.sensor_size; offsetY ++) {
+            let sample = boundary_texel(vec2<i32>(sensorCentreX + offsetX, sensorCentreY + offsetY));
+            let pixel_vec = textureLoad(texture, sample);
+            // Own trails attract and the other species' trails repel
+			sum += 2.0 * dot(pixel_vec, own) - dot(pixel_vec, simulated);
+            // The food map is greyscale, every species is drawn to it
+            sum += params.food_weight * textureLoad(texture_second, sample).x;
 		}
 	}
 
@@ -111,23 +312,28 @@ fn sense(agent: Agent, sensor_angle_spacing: f32) -> f32 {
 
 @compute @workgroup_size(512, 1, 1)
 fn update(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&agents)) {
+        return;
+    }
+
     // let random = hash(id.x * params.width + id.x + hash(id.x + u32(params.time * params.delta * 100000.123)) * u32(params.seed * 1200.90));
 
     let agent_id = id.x;
     let agent = &agents[agent_id];
+    let settings = species[(*agent).species];
     let location = vec2<i32>(i32((*agent).position.x), i32((*agent).position.y));
 
 
     let random = hash(id.x * u32(params.width) + hash(u32((*agent).position.x * (*agent).position.y)) + u32(params.time * 100000.0));
 
     let random_steer_strength = randomFloat(random);
-	let turn_speed = params.turn_speed * 2.0 * pi;
+	let turn_speed = settings.turn_speed * 2.0 * pi;
 
 	// Steer based on sensory data
-	let sensorAngleRad = params.sensor_angle_spacing * (pi / 180.0);
-	let weightForward = sense((*agent), 0.0);
-	let weightLeft = sense((*agent), sensorAngleRad);
-	let weightRight = sense((*agent), -sensorAngleRad);
+	let sensorAngleRad = settings.sensor_angle_spacing * (pi / 180.0);
+	let weightForward = sense((*agent), settings, 0.0);
+	let weightLeft = sense((*agent), settings, sensorAngleRad);
+	let weightRight = sense((*agent), settings, -sensorAngleRad);
 
     // Continue in same direction
 	if (weightForward > weightLeft && weightForward > weightRight) {
@@ -147,20 +353,49 @@ fn update(@builtin(global_invocation_id) id: vec3<u32>) {
 
     let direction = vec2<f32>(cos((*agent).angle), sin((*agent).angle));
     // Movement to new position
-    var new_pos = (*agent).position + direction * params.move_speed * params.delta;
-
-    if (new_pos.x < 0.0 || new_pos.x >= f32(params.width) || new_pos.y < 0.0 || new_pos.y >= f32(params.height)) {
-        (*agent).angle = randomFloat(random) * 2.0 * pi;
-        let new_direction = vec2<f32>(cos((*agent).angle), sin((*agent).angle));
-        new_pos = (*agent).position + new_direction * params.move_speed * params.delta;
+    var new_pos = (*agent).position + direction * settings.move_speed * params.delta;
+
+    // The repel brush pushes agents out and turns them away from the cursor
+    let away = (*agent).position - vec2<f32>(params.brush_x, params.brush_y);
+    let brush_distance = length(away);
+    if (params.brush_tool == BRUSH_REPEL && brush_distance < params.brush_radius && brush_distance > 0.0) {
+        let falloff = 1.0 - brush_distance / params.brush_radius;
+        (*agent).angle = atan2(away.y, away.x);
+        new_pos += away / brush_distance * params.brush_strength * params.brush_radius * falloff * params.delta;
+    }
 
-        // new_pos.x = min(f32(params.width) - 1.0, max(0.0, new_pos.x));
-        // new_pos.y = min(f32(params.height) - 1.0, max(0.0, new_pos.y));
+    let size = vec2<f32>(f32(params.width), f32(params.height));
+    // Keeps positions that land exactly on the far edge inside the texture
+    let max_pos = size - vec2<f32>(0.001);
+
+    if (new_pos.x < 0.0 || new_pos.x >= size.x || new_pos.y < 0.0 || new_pos.y >= size.y) {
+        if (params.boundary_mode == BOUNDARY_WRAP) {
+            new_pos = min(new_pos - floor(new_pos / size) * size, max_pos);
+        } else if (params.boundary_mode == BOUNDARY_REFLECT) {
+            if (new_pos.x < 0.0 || new_pos.x >= size.x) {
+                (*agent).angle = pi - (*agent).angle;
+            }
+            if (new_pos.y < 0.0 || new_pos.y >= size.y) {
+                (*agent).angle = -(*agent).angle;
+            }
+            new_pos = select(new_pos, -new_pos, new_pos < vec2<f32>(0.0));
+            new_pos = select(new_pos, 2.0 * size - new_pos, new_pos >= size);
+            new_pos = clamp(new_pos, vec2<f32>(0.0), max_pos);
+        } else if (params.boundary_mode == BOUNDARY_CLAMP) {
+            // Keeps the heading, so the agent slides along the edge
+            new_pos = clamp(new_pos, vec2<f32>(0.0), max_pos);
+        } else {
+            (*agent).angle = randomFloat(random) * 2.0 * pi;
+            let new_direction = vec2<f32>(cos((*agent).angle), sin((*agent).angle));
+            new_pos = (*agent).position + new_direction * settings.move_speed * params.delta;
+
+            // new_pos.x = min(f32(params.width) - 1.0, max(0.0, new_pos.x));
+            // new_pos.y = min(f32(params.height) - 1.0, max(0.0, new_pos.y));
+        }
     };
 
     (*agent).position = new_pos;
 
-    // storageBarrier();
-
-    textureStore(texture, location, vec4<f32>(params.color.xyz, 1.0));
+    // Keeps the deposits of other species on the same texel, unless they land in the same step
+    textureStore(texture_out, location, max(textureLoad(texture_out, location), species_channel((*agent).species)));
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+// The front trail texture from before the window was resized
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+// Both resized trail textures get the same trail, so it does not matter which one is in front
+@group(0) @binding(2)
+var target_front: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(3)
+var target_back: texture_storage_2d<rgba16float, write>;
+
+struct Agent {
+    position: vec2<f32>,
+    angle: f32,
+    species: u32,
+};
+
+@group(0) @binding(4)
+var<storage, read_write> agents: array<Agent>;
+
+@compute @workgroup_size(16, 16, 1)
+fn rescale(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(target_front);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+
+    let uv = (vec2<f32>(location) + 0.5) / vec2<f32>(size);
+    let color = textureSampleLevel(source, source_sampler, uv, 0.0);
+
+    textureStore(target_front, location, color);
+    textureStore(target_back, location, color);
+}
+
+@compute @workgroup_size(512, 1, 1)
+fn clamp_agents(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&agents)) {
+        return;
+    }
+
+    let size = vec2<f32>(textureDimensions(target_front));
+    agents[id.x].position = clamp(agents[id.x].position, vec2<f32>(0.0), size - vec2<f32>(0.001));
+}
@@ -1,37 +1,74 @@
 @group(0) @binding(0)
-var texture: texture_storage_2d<rgba8unorm, read_write>;
+var texture: texture_storage_2d<rgba16float, read_write>;
 
 @group(0) @binding(1)
 var texture_second: texture_storage_2d<rgba8unorm, read_write>;
 
 struct Agent {
     position: vec2<f32>,
-    angle: f32
+    angle: f32,
+    species: u32,
 };
 
 @group(0) @binding(2)
 var<storage, read_write> agents: array<Agent>;
 
 struct Params {
-    color: vec4<f32>,
     blur_mask: vec4<f32>,
     width: u32,
     height: u32,
-    mode: u32,
+    species_count: u32,
     trail_weight: f32,
     decay_rate: f32,
     time: f32,
     delta: f32,
     salt: u32,
+    blur_radius: u32,
+    blur_sigma: f32,
+    boundary_mode: u32,
+    food_weight: f32,
+    palette_enabled: u32,
+    brush_x: f32,
+    brush_y: f32,
+    brush_radius: f32,
+    brush_strength: f32,
+    brush_tool: u32,
+    brush_species: u32,
+    };
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+struct Species {
+    color: vec4<f32>,
+    mode: u32,
     move_speed: f32,
     turn_speed: f32,
     sensor_angle_spacing: f32,
     sensor_offset_distance: f32,
     sensor_size: u32,
-    };
+    follow_mask_gradient: u32,
+    spawn_radius: f32,
+    spawn_count: u32,
+    spawn_heading: u32,
+    spawn_vertical: u32,
+};
 
-@group(0) @binding(3)
-var<uniform> params: Params;
+@group(0) @binding(4)
+var<storage, read> species: array<Species>;
+
+// Passes read the trail from `texture` and write the result here, then the two swap. Only the
+// texel a pass writes may be read back, the others are written concurrently
+@group(0) @binding(5)
+var texture_out: texture_storage_2d<rgba16float, read_write>;
+
+// What is shown on screen, written only by `color`
+@group(0) @binding(7)
+var display: texture_storage_2d<rgba8unorm, write>;
+
+// The gradient `color` maps trail intensity through
+@group(0) @binding(8)
+var palette_lut: texture_1d<f32>;
 
 fn hash(value: u32) -> u32 {
     var state = value;
@@ -48,48 +85,98 @@ fn randomFloat(value: u32) -> f32 {
     return f32(hash(value)) / 4294967295.0;
 }
 
-fn get_color(location: vec2<i32>, offset_x: i32, offset_y: i32) -> vec4<f32> {
-    return textureLoad(texture, location + vec2<i32>(offset_x, offset_y));
+// Boundary modes, see `BoundaryMode`
+let BOUNDARY_WRAP = 1u;
+let BOUNDARY_REFLECT = 2u;
+
+// The texel that is read in place of `location` when it lies outside the texture
+fn boundary_texel(location: vec2<i32>) -> vec2<i32> {
+    let size = vec2<i32>(i32(params.width), i32(params.height));
+    var texel = location;
+    if (params.boundary_mode == BOUNDARY_WRAP) {
+        texel = ((texel % size) + size) % size;
+    } else if (params.boundary_mode == BOUNDARY_REFLECT) {
+        texel = select(texel, -texel - 1, texel < vec2<i32>(0));
+        texel = select(texel, 2 * size - texel - 1, texel >= size);
+    }
+    return clamp(texel, vec2<i32>(0), size - 1);
 }
 
-fn average_neighbors(location: vec2<i32>) -> vec4<f32> {
-    let neighbor_sum: vec4<f32> = get_color(location, -1, -1) +
-           get_color(location, -1,  0) +
-           get_color(location, -1,  1) +
-           get_color(location,  0, -1) +
-           get_color(location,  0,  1) +
-           get_color(location,  1, -1) +
-           get_color(location,  1,  0) +
-           get_color(location,  1,  1);
-
-    return neighbor_sum * vec4<f32>(0.125) * vec4<f32>(params.blur_mask.xyz, 1.0);
+// Each species deposits into its own channel of the trail
+fn species_channel(index: u32) -> vec4<f32> {
+    return select(vec4<f32>(0.0), vec4<f32>(1.0), vec4<u32>(0u, 1u, 2u, 3u) == vec4<u32>(index));
+}
+
+fn get_color(location: vec2<i32>) -> vec4<f32> {
+    return textureLoad(texture, boundary_texel(location));
+}
+
+// One dimension of the gaussian, `direction` is (1, 0) or (0, 1)
+fn gaussian_blur(location: vec2<i32>, direction: vec2<i32>) -> vec4<f32> {
+    let radius = i32(params.blur_radius);
+    let two_sigma_sq = 2.0 * max(params.blur_sigma * params.blur_sigma, 0.0001);
+
+    var sum = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var offset = -radius; offset <= radius; offset++) {
+        let weight = exp(-f32(offset * offset) / two_sigma_sq);
+        sum += get_color(location + direction * offset) * weight;
+        total_weight += weight;
+    }
+
+    return sum / total_weight;
 }
 
 @compute @workgroup_size(16, 16, 1)
-fn blur(@builtin(global_invocation_id) id: vec3<u32>) {
+fn blur_horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
     if (id.x < u32(0) || id.x >= params.width || id.y < u32(0) || id.y >= params.height) {
         return;
     };
     let location = vec2<i32>(i32(id.x), i32(id.y));
 
-    var sum: vec4<f32>;
+    textureStore(texture_out, location, gaussian_blur(location, vec2<i32>(1, 0)));
+}
 
-    // for (var offsetX = -1; offsetX <= 1; offsetX ++) {
-	// 	for (var offsetY = -1; offsetY <= 1; offsetY ++) {
-	// 		let sampleX = min(i32(params.width) - 1, max(0, offsetX));
-	// 		let sampleY = min(i32(params.height) - 1, max(0, offsetY));
-            
-	// 		sum += textureLoad(texture, vec2<i32>(sampleX, sampleY));
-	// 	}
-	// }
+// The trail weight only applies once, here in the second pass. The back texture still holds the
+// trail from before `blur_horizontal`, which is what the blur is blended into.
+@compute @workgroup_size(16, 16, 1)
+fn blur_vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x < u32(0) || id.x >= params.width || id.y < u32(0) || id.y >= params.height) {
+        return;
+    };
+    let location = vec2<i32>(i32(id.x), i32(id.y));
 
-    let original_color = textureLoad(texture, location);
-    let blurred_color = average_neighbors(location);
+    let original_color = textureLoad(texture_out, location);
+    let blurred_color = gaussian_blur(location, vec2<i32>(0, 1));
     let color = (original_color + (blurred_color - original_color) * params.trail_weight);
 
-    storageBarrier();
+    textureStore(texture_out, location, color);
+}
+
+// Brush tools, see `BrushTool`
+let BRUSH_DEPOSIT = 1u;
+let BRUSH_ERASE = 2u;
+
+@compute @workgroup_size(16, 16, 1)
+fn brush(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x < u32(0) || id.x >= params.width || id.y < u32(0) || id.y >= params.height) {
+        return;
+    };
+    let location = vec2<i32>(i32(id.x), i32(id.y));
 
-    textureStore(texture, location, color);
+    var color = textureLoad(texture, location);
+    let distance = length(vec2<f32>(location) + 0.5 - vec2<f32>(params.brush_x, params.brush_y));
+    if (distance < params.brush_radius) {
+        let falloff = 1.0 - distance / params.brush_radius;
+        let amount = clamp(params.brush_strength * params.delta * falloff, 0.0, 1.0);
+        if (params.brush_tool == BRUSH_DEPOSIT) {
+            color = mix(color, max(color, species_channel(params.brush_species)), amount);
+        } else if (params.brush_tool == BRUSH_ERASE) {
+            color = mix(color, vec4<f32>(0.0), amount);
+        }
+    }
+
+    textureStore(texture_out, location, color);
 }
 
 @compute @workgroup_size(16, 16, 1)
@@ -103,9 +190,7 @@ fn decay(@builtin(global_invocation_id) id: vec3<u32>) {
 
     let new_color = max(vec4<f32>(0.0), color - vec4<f32>(params.decay_rate * params.delta));
 
-    // storageBarrier();
-
-    textureStore(texture, location, new_color);
+    textureStore(texture_out, location, new_color);
 }
 
 @compute @workgroup_size(16, 16, 1)
@@ -117,7 +202,7 @@ fn show_random(@builtin(global_invocation_id) id: vec3<u32>) {
 
     let pixel_index = id.x * params.width + id.y;
     let random = randomFloat(pixel_index);
-    textureStore(texture, location, vec4<f32>(random ,random,random, 1.0));
+    textureStore(texture_out, location, vec4<f32>(random ,random,random, 1.0));
 }
 
 @compute @workgroup_size(16, 16, 1)
@@ -127,11 +212,30 @@ fn color(@builtin(global_invocation_id) id: vec3<u32>) {
     };
     let location = vec2<i32>(i32(id.x), i32(id.y));
 
-    let map = textureLoad(texture_second, location);
-
-    let color = vec4<f32>((params.color.xyz * map.xyz), 1.0);
-
-    // storageBarrier();
-
-    textureStore(texture, location, color);
+    let trail = textureLoad(texture, location);
+    if (params.palette_enabled == u32(0)) {
+        // Every species in its color, tinted toward the blur mask as its trail fades
+        var color = vec3<f32>(0.0);
+        for (var i = 0u; i < params.species_count; i++) {
+            let intensity = clamp(dot(trail, species_channel(i)), 0.0, 1.0);
+            let tint = mix(params.blur_mask.xyz, vec3<f32>(1.0), intensity);
+            color += intensity * species[i].color.xyz * tint;
+        }
+        textureStore(display, location, vec4<f32>(color, 1.0));
+        return;
+    }
+
+    // The strongest trail on the texel, the palette does not tell species apart. Blend the two
+    // texels around it, the LUT can not be sampled with a filter.
+    var strongest = 0.0;
+    for (var i = 0u; i < params.species_count; i++) {
+        strongest = max(strongest, dot(trail, species_channel(i)));
+    }
+    let intensity = clamp(strongest, 0.0, 1.0);
+    let position = intensity * f32(textureDimensions(palette_lut) - 1);
+    let low = i32(floor(position));
+    let high = min(low + 1, textureDimensions(palette_lut) - 1);
+    let color = mix(textureLoad(palette_lut, low, 0), textureLoad(palette_lut, high, 0), fract(position));
+
+    textureStore(display, location, vec4<f32>(color.xyz, 1.0));
 }
\ No newline at end of file
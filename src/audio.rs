@@ -0,0 +1,548 @@
+//! Audio-reactive modulation of `SimParams` fields.
+//!
+//! Every frame the last [`FFT_SIZE`] samples of the audio are analyzed into their RMS and the
+//! amplitudes of [`BANDS`] log-spaced frequency bands. The audio either comes from a WAV file,
+//! which is only analyzed and not played, or from the default input device.
+//!
+//! A WAV file follows the simulation clock, so it stays in sync while paused, restarted or
+//! recorded, play the same file elsewhere to hear it. Live input always analyzes the newest
+//! samples.
+//!
+//! Modulations add on top of what the sliders and `update_params` set through [`ParamOffsets`],
+//! only the exported params see them, clamped to each field's range.
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_egui::{
+    egui::{self, Button, Checkbox, ComboBox, DragValue, ProgressBar},
+    EguiContext,
+};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossbeam_channel::Receiver;
+
+use crate::{
+    param_target::{ParamOffsets, ParamTarget},
+    EguiState, SimParams, SimSettings, SimState,
+};
+
+/// Samples analyzed per frame, a power of two
+pub const FFT_SIZE: usize = 2048;
+pub const BANDS: usize = 8;
+const LOWEST_BAND: f32 = 40.0;
+const HIGHEST_BAND: f32 = 16000.0;
+
+/// Mono samples of a decoded WAV file
+pub struct WavFile {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl WavFile {
+    /// Reads PCM with 8 to 32 bits and float samples, channels are mixed down to mono
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("Not a WAV file".to_string());
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+
+        let mut format = None;
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            let start = offset + 8;
+            // Writers that stream leave the size of the data chunk unset
+            let end = (start + size as usize).min(bytes.len());
+            match id {
+                b"fmt " if end - start >= 16 => {
+                    let mut tag = u16_at(start);
+                    // WAVE_FORMAT_EXTENSIBLE keeps the actual format in its subformat
+                    if tag == 0xfffe && end - start >= 26 {
+                        tag = u16_at(start + 24);
+                    }
+                    let channels = u16_at(start + 2) as usize;
+                    let sample_rate =
+                        u32::from_le_bytes(bytes[start + 4..start + 8].try_into().unwrap());
+                    let bits = u16_at(start + 14);
+                    format = Some((tag, channels, sample_rate, bits));
+                }
+                b"data" => {
+                    let (tag, channels, sample_rate, bits) =
+                        format.ok_or("The data chunk comes before the format chunk")?;
+                    let samples = decode_samples(&bytes[start..end], tag, bits)?;
+                    if channels == 0 || sample_rate == 0 {
+                        return Err("Invalid format chunk".to_string());
+                    }
+                    return Ok(Self {
+                        samples: samples
+                            .chunks_exact(channels)
+                            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                            .collect(),
+                        sample_rate,
+                    });
+                }
+                _ => {}
+            }
+            // Chunks are padded to even sizes
+            offset = start + size as usize + (size as usize & 1);
+        }
+        Err("No data chunk".to_string())
+    }
+}
+
+fn decode_samples(data: &[u8], tag: u16, bits: u16) -> Result<Vec<f32>, String> {
+    let samples = match (tag, bits) {
+        (1, 8) => data.iter().map(|&s| (s as f32 - 128.0) / 128.0).collect(),
+        (1, 16) => data
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+            .collect(),
+        (1, 24) => data
+            .chunks_exact(3)
+            .map(|s| i32::from_le_bytes([0, s[0], s[1], s[2]]) as f32 / 2147483648.0)
+            .collect(),
+        (1, 32) => data
+            .chunks_exact(4)
+            .map(|s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2147483648.0)
+            .collect(),
+        (3, 32) => data
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+            .collect(),
+        (3, 64) => data
+            .chunks_exact(8)
+            .map(|s| f64::from_le_bytes(s.try_into().unwrap()) as f32)
+            .collect(),
+        _ => return Err(format!("Unsupported WAV format {} with {} bits", tag, bits)),
+    };
+    Ok(samples)
+}
+
+/// In place radix-2 FFT, the length has to be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AudioFeatures {
+    pub rms: f32,
+    /// Amplitude of each band, a full scale sine reads about 1 in its band
+    pub bands: [f32; BANDS],
+}
+
+impl AudioFeatures {
+    /// `samples` are the newest ones, missing samples count as silence
+    pub fn analyze(samples: &[f32], sample_rate: u32) -> Self {
+        let samples = &samples[samples.len().saturating_sub(FFT_SIZE)..];
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+
+        let mut re = [0.0; FFT_SIZE];
+        let mut im = [0.0; FFT_SIZE];
+        let padding = FFT_SIZE - samples.len();
+        for (index, sample) in samples.iter().enumerate() {
+            let index = padding + index;
+            let hann = 0.5 - 0.5 * (2.0 * PI * index as f32 / FFT_SIZE as f32).cos();
+            re[index] = sample * hann;
+        }
+        fft(&mut re, &mut im);
+
+        // The Hann window halves the amplitude and spreads the energy of a sine over 1.5 bins,
+        // half of it ends up in the mirrored bins
+        let scale = 4.0 / FFT_SIZE as f32 * (2.0f32 / 3.0).sqrt();
+        let nyquist = sample_rate as f32 / 2.0;
+        let highest = HIGHEST_BAND.min(nyquist);
+        let bin = |frequency: f32| {
+            ((frequency / nyquist * (FFT_SIZE / 2) as f32) as usize).min(FFT_SIZE / 2 - 1)
+        };
+        let mut bands = [0.0; BANDS];
+        for (index, band) in bands.iter_mut().enumerate() {
+            let edge =
+                |i: usize| LOWEST_BAND * (highest / LOWEST_BAND).powf(i as f32 / BANDS as f32);
+            let low = bin(edge(index));
+            let high = bin(edge(index + 1)).max(low + 1);
+            *band = (low..high)
+                .map(|k| (re[k] * re[k] + im[k] * im[k]) * scale * scale)
+                .sum::<f32>()
+                .sqrt();
+        }
+        Self { rms, bands }
+    }
+
+    fn get(&self, feature: AudioFeature) -> f32 {
+        match feature {
+            AudioFeature::Rms => self.rms,
+            AudioFeature::Band(index) => self.bands[index.min(BANDS - 1)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFeature {
+    Rms,
+    Band(usize),
+}
+
+impl AudioFeature {
+    fn label(&self) -> String {
+        match self {
+            Self::Rms => "RMS".to_string(),
+            Self::Band(index) => format!("Band {}", index + 1),
+        }
+    }
+}
+
+enum AudioSource {
+    None,
+    File {
+        wav: WavFile,
+        /// Seconds into the file
+        position: f64,
+    },
+    Live {
+        receiver: Receiver<f32>,
+        sample_rate: u32,
+        /// The newest samples
+        buffer: VecDeque<f32>,
+    },
+}
+
+#[derive(Resource)]
+pub struct AudioAnalysis {
+    pub features: AudioFeatures,
+    source: AudioSource,
+    /// WAV file, relative to `assets`
+    pub path: String,
+    /// Start the file over at its end, otherwise it stays silent
+    pub looping: bool,
+    pub status: String,
+}
+
+impl Default for AudioAnalysis {
+    fn default() -> Self {
+        Self {
+            features: AudioFeatures::default(),
+            source: AudioSource::None,
+            path: "audio/music.wav".to_string(),
+            looping: true,
+            status: String::new(),
+        }
+    }
+}
+
+impl AudioAnalysis {
+    fn load_file(&mut self) -> Result<(), String> {
+        let file = FileAssetIo::get_base_path().join("assets").join(&self.path);
+        let bytes = std::fs::read(&file).map_err(|err| format!("{}: {}", file.display(), err))?;
+        let wav = WavFile::decode(&bytes).map_err(|err| format!("{}: {}", file.display(), err))?;
+        self.status = format!(
+            "{}: {:.1}s at {} Hz",
+            self.path,
+            wav.samples.len() as f32 / wav.sample_rate as f32,
+            wav.sample_rate
+        );
+        self.source = AudioSource::File { wav, position: 0.0 };
+        Ok(())
+    }
+}
+
+/// Keeps the input stream running, which is not `Send` on every backend
+#[derive(Default)]
+pub struct AudioStream(Option<cpal::Stream>);
+
+fn open_input(sender: crossbeam_channel::Sender<f32>) -> Result<(cpal::Stream, u32), String> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or("No audio input device")?;
+    let config = device
+        .default_input_config()
+        .map_err(|err| err.to_string())?;
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+    let on_error = |err| error!("Audio input failed: {}", err);
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => {
+            device.build_input_stream(&config.into(), forward::<f32>(sender, channels), on_error)
+        }
+        cpal::SampleFormat::I16 => {
+            device.build_input_stream(&config.into(), forward::<i16>(sender, channels), on_error)
+        }
+        cpal::SampleFormat::U16 => {
+            device.build_input_stream(&config.into(), forward::<u16>(sender, channels), on_error)
+        }
+    }
+    .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok((stream, sample_rate))
+}
+
+/// Mixes the input down to mono and sends it to the analysis
+fn forward<T: cpal::Sample>(
+    sender: crossbeam_channel::Sender<f32>,
+    channels: usize,
+) -> impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static {
+    move |data, _| {
+        for frame in data.chunks_exact(channels) {
+            let sample = frame.iter().map(|s| s.to_f32()).sum::<f32>() / channels as f32;
+            let _ = sender.send(sample);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AudioModulation {
+    pub feature: AudioFeature,
+    pub target: ParamTarget,
+    pub gain: f32,
+    pub offset: f32,
+    /// Seconds the feature takes to rise or fall most of the way to a new value
+    pub smoothing: f32,
+    /// The feature after smoothing
+    value: f32,
+}
+
+impl AudioModulation {
+    fn new(target: ParamTarget) -> Self {
+        let (min, max) = target.range();
+        Self {
+            feature: AudioFeature::Rms,
+            target,
+            // Loud passages move the field by a quarter of its range, rather than pinning it
+            gain: (max - min) * 0.25,
+            offset: 0.0,
+            smoothing: 0.1,
+            value: 0.0,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct AudioModulations {
+    pub modulations: Vec<AudioModulation>,
+}
+
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AudioAnalysis>()
+            .init_resource::<AudioModulations>()
+            .init_non_send_resource::<AudioStream>()
+            .add_system(analyze_audio)
+            .add_system(modulate_params.after(analyze_audio))
+            .add_system(ui_audio);
+    }
+}
+
+fn analyze_audio(mut analysis: ResMut<AudioAnalysis>, settings: Res<SimSettings>, time: Res<Time>) {
+    let looping = analysis.looping;
+    let features = match &mut analysis.source {
+        AudioSource::None => AudioFeatures::default(),
+        AudioSource::File { wav, position } => {
+            match settings.state {
+                SimState::Initialize => *position = 0.0,
+                // Time scale is left out, the file plays at its own speed
+                SimState::Playing => *position += settings.frame_delta(time.delta_seconds()) as f64,
+                SimState::Paused => {}
+            }
+            let duration = wav.samples.len() as f64 / wav.sample_rate as f64;
+            if looping && duration > 0.0 && *position >= duration {
+                *position %= duration;
+            }
+            let end = ((*position * wav.sample_rate as f64) as usize).min(wav.samples.len());
+            AudioFeatures::analyze(&wav.samples[..end], wav.sample_rate)
+        }
+        AudioSource::Live {
+            receiver,
+            sample_rate,
+            buffer,
+        } => {
+            buffer.extend(receiver.try_iter());
+            let excess = buffer.len().saturating_sub(FFT_SIZE);
+            buffer.drain(..excess);
+            AudioFeatures::analyze(buffer.make_contiguous(), *sample_rate)
+        }
+    };
+    // Silence past the end of a file is left unchanged
+    if analysis.features != features {
+        analysis.features = features;
+    }
+}
+
+/// Sets the offsets the modulations add to their targets, `prepare_params` adds them on export
+fn modulate_params(
+    mut modulations: ResMut<AudioModulations>,
+    mut param_offsets: ResMut<ParamOffsets>,
+    analysis: Res<AudioAnalysis>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    let mut offsets = ParamOffsets::default();
+    for modulation in modulations.modulations.iter_mut() {
+        let feature = analysis.features.get(modulation.feature);
+        let blend = if modulation.smoothing > 0.0 {
+            1.0 - (-dt / modulation.smoothing).exp()
+        } else {
+            1.0
+        };
+        modulation.value += (feature - modulation.value) * blend;
+        offsets.add(
+            modulation.target,
+            modulation.offset + modulation.gain * modulation.value,
+        );
+    }
+    if *param_offsets != offsets {
+        *param_offsets = offsets;
+    }
+}
+
+fn ui_audio(
+    mut egui_context: ResMut<EguiContext>,
+    mut analysis: ResMut<AudioAnalysis>,
+    mut modulations: ResMut<AudioModulations>,
+    mut stream: NonSendMut<AudioStream>,
+    sim_params: Res<SimParams>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    egui::Window::new("Audio").show(egui_context.ctx_mut(), |ui| {
+        ui.text_edit_singleline(&mut analysis.path);
+        ui.horizontal(|ui| {
+            if ui.add(Button::new("Load WAV")).clicked() {
+                stream.0 = None;
+                if let Err(err) = analysis.load_file() {
+                    analysis.status = err;
+                }
+            }
+            if ui.add(Button::new("Live Input")).clicked() {
+                let (sender, receiver) = crossbeam_channel::unbounded();
+                match open_input(sender) {
+                    Ok((input, sample_rate)) => {
+                        stream.0 = Some(input);
+                        analysis.source = AudioSource::Live {
+                            receiver,
+                            sample_rate,
+                            buffer: VecDeque::with_capacity(FFT_SIZE),
+                        };
+                        analysis.status = format!("Live input at {} Hz", sample_rate);
+                    }
+                    Err(err) => analysis.status = err,
+                }
+            }
+            if ui.add(Button::new("Stop")).clicked() {
+                stream.0 = None;
+                analysis.source = AudioSource::None;
+                analysis.status.clear();
+            }
+        });
+        ui.add(Checkbox::new(&mut analysis.looping, "Loop"));
+        if !analysis.status.is_empty() {
+            ui.label(&analysis.status);
+        }
+
+        let features = analysis.features;
+        ui.add(ProgressBar::new(features.rms.min(1.0)).text("RMS"));
+        ui.horizontal(|ui| {
+            for band in features.bands {
+                ui.add(ProgressBar::new(band.min(1.0)).desired_width(24.0));
+            }
+        });
+
+        ui.separator();
+        let targets = ParamTarget::all(sim_params.species_count as usize);
+        let mut removed = None;
+        for (index, modulation) in modulations.modulations.iter_mut().enumerate() {
+            ui.push_id(index, |ui| {
+                ui.horizontal(|ui| {
+                    ComboBox::from_id_source("feature")
+                        .selected_text(modulation.feature.label())
+                        .show_ui(ui, |ui| {
+                            for feature in std::iter::once(AudioFeature::Rms)
+                                .chain((0..BANDS).map(AudioFeature::Band))
+                            {
+                                ui.selectable_value(
+                                    &mut modulation.feature,
+                                    feature,
+                                    feature.label(),
+                                );
+                            }
+                        });
+                    ComboBox::from_id_source("target")
+                        .selected_text(modulation.target.label())
+                        .show_ui(ui, |ui| {
+                            for target in targets.iter() {
+                                ui.selectable_value(
+                                    &mut modulation.target,
+                                    *target,
+                                    target.label(),
+                                );
+                            }
+                        });
+                    if ui.add(Button::new("x")).clicked() {
+                        removed = Some(index);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(
+                        DragValue::new(&mut modulation.gain)
+                            .speed(0.01)
+                            .prefix("gain: "),
+                    );
+                    ui.add(
+                        DragValue::new(&mut modulation.offset)
+                            .speed(0.01)
+                            .prefix("offset: "),
+                    );
+                    ui.add(
+                        DragValue::new(&mut modulation.smoothing)
+                            .speed(0.01)
+                            .clamp_range(0.0..=5.0)
+                            .prefix("smoothing: ")
+                            .suffix("s"),
+                    );
+                });
+            });
+        }
+        if let Some(index) = removed {
+            modulations.modulations.remove(index);
+        }
+        if ui.add(Button::new("Add Modulation")).clicked() {
+            modulations
+                .modulations
+                .push(AudioModulation::new(ParamTarget::DecayRate));
+        }
+    });
+}
@@ -0,0 +1,126 @@
+//! Painting on the canvas with the mouse.
+//!
+//! Left-drag uses the tool selected in the UI, right-drag always erases and shift-drag repels
+//! agents. The stroke is handed to the kernels through `SimParams::brush` in texture space, the
+//! `brush` pass paints the trail and `update` pushes agents out of the repel brush.
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+
+use crate::{EguiState, SimParams, TrailSprite};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushTool {
+    /// Paints the trail in the color of the selected species
+    Deposit = 1,
+    Erase = 2,
+    /// Pushes agents out of the brush and turns them away from the cursor
+    Repel = 3,
+}
+
+/// Brush settings from the UI.
+#[derive(Resource)]
+pub struct Brush {
+    pub tool: BrushTool,
+    /// In texels
+    pub radius: f32,
+    /// How much of the trail is painted per second, or how many radii per second agents are
+    /// pushed
+    pub strength: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tool: BrushTool::Deposit,
+            radius: 30.0,
+            strength: 5.0,
+        }
+    }
+}
+
+/// The brush as the kernels see it for a frame.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct BrushStroke {
+    /// Texture space position of the cursor
+    pub position: Vec2,
+    pub radius: f32,
+    pub strength: f32,
+    /// The tool that is painting, `None` while no button is held
+    pub tool: Option<BrushTool>,
+    /// The species `Deposit` paints with
+    pub species: u32,
+}
+
+pub struct BrushPlugin;
+
+impl Plugin for BrushPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Brush>().add_system(update_brush);
+    }
+}
+
+/// Converts a window position to texture space, through the camera and the trail sprite.
+fn window_to_texture(
+    cursor: Vec2,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+    (sprite, sprite_transform): (&Sprite, &GlobalTransform),
+) -> Option<Vec2> {
+    let world = camera.viewport_to_world(camera_transform, cursor)?.origin;
+    let local = sprite_transform
+        .compute_matrix()
+        .inverse()
+        .transform_point3(world)
+        .truncate();
+    let size = sprite.custom_size?;
+    // The sprite is centered on its transform, and the texture starts at its top left corner
+    Some(Vec2::new(local.x + size.x / 2.0, size.y / 2.0 - local.y))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_brush(
+    brush: Res<Brush>,
+    egui_state: Res<EguiState>,
+    mut egui_context: ResMut<EguiContext>,
+    mut sim_params: ResMut<SimParams>,
+    windows: Res<Windows>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    sprites: Query<(&Sprite, &GlobalTransform), With<TrailSprite>>,
+) {
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let mut tool = if buttons.pressed(MouseButton::Right) {
+        Some(BrushTool::Erase)
+    } else if buttons.pressed(MouseButton::Left) {
+        Some(if shift { BrushTool::Repel } else { brush.tool })
+    } else {
+        None
+    };
+    // Dragging a slider should not paint underneath the window
+    if egui_context.ctx_mut().wants_pointer_input() || egui_context.ctx_mut().is_using_pointer() {
+        tool = None;
+    }
+
+    let position = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+        .zip(cameras.get_single().ok())
+        .zip(sprites.get_single().ok())
+        .and_then(|((cursor, camera), sprite)| window_to_texture(cursor, camera, sprite));
+    if position.is_none() {
+        tool = None;
+    }
+
+    let stroke = BrushStroke {
+        position: position.filter(|_| tool.is_some()).unwrap_or_default(),
+        radius: brush.radius,
+        strength: brush.strength,
+        tool,
+        species: egui_state.selected_species as u32,
+    };
+    // Only touched when it changes, so the params are not extracted every frame
+    if sim_params.brush != stroke {
+        sim_params.brush = stroke;
+    }
+}
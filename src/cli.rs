@@ -0,0 +1,310 @@
+//! Command line options for launching the simulation.
+//!
+//! Options that are left out fall back to their defaults. An argument that is not in [`USAGE`], or
+//! a value that is missing or fails to parse, stops the launch with the usage.
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use bevy::{
+    prelude::*,
+    window::{PresentMode, WindowMode},
+};
+
+use crate::{
+    osc::DEFAULT_OSC_PORT, preset::DEFAULT_PRESET, DEFAULT_NUM_AGENTS, FIXED_DELTA,
+    FIXED_DELTA_RANGE, MAX_SUBSTEPS,
+};
+
+const DEFAULT_WINDOW_SIZE: (f32, f32) = (1280.0, 720.0);
+const DEFAULT_HEADLESS_SIZE: (f32, f32) = (1920.0, 1080.0);
+
+pub const USAGE: &str = "\
+Usage: bevy_shader_test [OPTIONS]
+
+Options:
+  --windowed               open a window instead of going fullscreen
+  --fullscreen             fullscreen on the current monitor, the default
+  --width <PX>             window width, or the video mode width in fullscreen
+  --height <PX>            window height, or the video mode height in fullscreen
+  --agents <N>             number of agents
+  --preset <PATH>          preset to start with, relative to assets
+  --seed <N>               seed of the first run, random by default
+  --present-mode <MODE>    fifo, mailbox, immediate, auto-vsync or auto-no-vsync
+  --zoom <SCALE>           camera scale, above 1 zooms out
+  --fixed-delta <SECONDS>  length of a fixed timestep, 1/60 by default
+  --substeps <N>           simulation steps per frame, or per fixed timestep, 1 to 8
+  --midi-port <NAME>       MIDI input port whose name contains NAME, the first port by default
+  --midi-virtual           open a virtual MIDI input port instead, on Linux and macOS
+  --osc-port <PORT>        UDP port to listen for OSC messages on, 9000 by default
+  --osc-bind <IP>          address to listen for OSC messages on, 127.0.0.1 by default so
+                           only this machine can send them, 0.0.0.0 for the whole network
+  --osc-target <IP:PORT>   where OSC state changes are sent
+  --headless               render frames to PNGs without a window, with --width, --height,
+                           --frames, --fps, --output and --cpu
+  --frames <N>             frames to render headless, 600 by default
+  --fps <N>                frames per second of simulated time when rendering headless
+  --output <DIR>           directory headless frames are written to, frames by default
+  --cpu                    simulate headless frames on the CPU even when there is an adapter
+  --help                   print this message";
+
+/// Options in `USAGE` that stand on their own
+const FLAGS: [&str; 6] = [
+    "--windowed",
+    "--fullscreen",
+    "--midi-virtual",
+    "--headless",
+    "--cpu",
+    "--help",
+];
+
+/// Options in `USAGE` that take the argument after them as their value
+const OPTIONS: [&str; 16] = [
+    "--width",
+    "--height",
+    "--agents",
+    "--preset",
+    "--seed",
+    "--present-mode",
+    "--zoom",
+    "--fixed-delta",
+    "--substeps",
+    "--midi-port",
+    "--osc-port",
+    "--osc-bind",
+    "--osc-target",
+    "--frames",
+    "--fps",
+    "--output",
+];
+
+/// `args` are the launch arguments without the program name
+pub fn has_arg(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+/// The value after `name`, `None` when the option is not given. Values can not start with `--`,
+/// that is the next option with the value left out.
+pub fn arg_value<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return match args.next() {
+                Some(value) if !value.starts_with("--") => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid value {} for {}", value, name)),
+                _ => Err(format!("{} needs a value", name)),
+            };
+        }
+    }
+    Ok(None)
+}
+
+/// Fails on the first argument that is neither a flag nor an option followed by its value
+fn check_args(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        } else if !FLAGS.contains(&arg.as_str()) {
+            return Err(format!("unknown argument {}", arg));
+        }
+    }
+    Ok(())
+}
+
+fn parse_present_mode(name: &str) -> Result<PresentMode, String> {
+    match name {
+        "fifo" => Ok(PresentMode::Fifo),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        "auto-vsync" => Ok(PresentMode::AutoVsync),
+        "auto-no-vsync" => Ok(PresentMode::AutoNoVsync),
+        _ => Err(format!("unknown present mode {}", name)),
+    }
+}
+
+#[derive(Debug, Clone, Resource)]
+pub struct LaunchOptions {
+    pub windowed: bool,
+    /// Window size, or the video mode to switch to in fullscreen, or the size of headless frames
+    pub size: Option<(f32, f32)>,
+    pub agents: u32,
+    /// Relative to `assets`
+    pub preset: String,
+    /// Random when not given
+    pub seed: Option<u32>,
+    pub present_mode: PresentMode,
+    pub zoom: f32,
+    /// Seconds of a fixed timestep
+    pub fixed_delta: f32,
+    pub substeps: u32,
+    pub midi_port: Option<String>,
+    pub midi_virtual: bool,
+    pub osc_port: u16,
+    /// Loopback unless opened up for other machines
+    pub osc_bind: IpAddr,
+    /// `ip:port`, nothing is sent without one
+    pub osc_target: Option<String>,
+    pub headless: bool,
+    /// Frames to render headless
+    pub frames: u32,
+    /// Frames per second of simulated time when rendering headless
+    pub fps: f32,
+    /// Directory headless frames are written to
+    pub output: PathBuf,
+    /// Simulate headless frames on the CPU even when there is an adapter
+    pub cpu: bool,
+}
+
+impl LaunchOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        check_args(args)?;
+        if has_arg(args, "--windowed") && has_arg(args, "--fullscreen") {
+            return Err("--windowed and --fullscreen can not be used together".to_string());
+        }
+        let headless = has_arg(args, "--headless");
+        let default_size = if headless {
+            DEFAULT_HEADLESS_SIZE
+        } else {
+            DEFAULT_WINDOW_SIZE
+        };
+        let size = match (
+            arg_value::<f32>(args, "--width")?,
+            arg_value::<f32>(args, "--height")?,
+        ) {
+            (None, None) => None,
+            (width, height) => Some((
+                width.unwrap_or(default_size.0),
+                height.unwrap_or(default_size.1),
+            )),
+        };
+        // Anything under a pixel would allocate empty textures
+        if let Some((width, height)) = size {
+            if width < 1.0 {
+                return Err(format!("invalid value {} for --width", width));
+            }
+            if height < 1.0 {
+                return Err(format!("invalid value {} for --height", height));
+            }
+        }
+        let present_mode = match arg_value::<String>(args, "--present-mode")? {
+            Some(name) => parse_present_mode(&name)?,
+            None => PresentMode::Fifo,
+        };
+        let fixed_delta = arg_value::<f32>(args, "--fixed-delta")?.unwrap_or(FIXED_DELTA);
+        if !FIXED_DELTA_RANGE.contains(&fixed_delta) {
+            return Err(format!("invalid value {} for --fixed-delta", fixed_delta));
+        }
+        let substeps = arg_value::<u32>(args, "--substeps")?.unwrap_or(1);
+        if !(1..=MAX_SUBSTEPS).contains(&substeps) {
+            return Err(format!("invalid value {} for --substeps", substeps));
+        }
+        let zoom = arg_value::<f32>(args, "--zoom")?.unwrap_or(1.0);
+        if zoom <= 0.0 {
+            return Err(format!("invalid value {} for --zoom", zoom));
+        }
+        let fps = arg_value::<f32>(args, "--fps")?.unwrap_or(60.0);
+        if fps <= 0.0 {
+            return Err(format!("invalid value {} for --fps", fps));
+        }
+
+        Ok(Self {
+            windowed: has_arg(args, "--windowed"),
+            size,
+            agents: arg_value(args, "--agents")?
+                .unwrap_or(DEFAULT_NUM_AGENTS)
+                .max(1),
+            preset: arg_value(args, "--preset")?.unwrap_or_else(|| DEFAULT_PRESET.to_string()),
+            seed: arg_value(args, "--seed")?,
+            present_mode,
+            zoom,
+            fixed_delta,
+            substeps,
+            midi_port: arg_value(args, "--midi-port")?,
+            midi_virtual: has_arg(args, "--midi-virtual"),
+            osc_port: arg_value(args, "--osc-port")?.unwrap_or(DEFAULT_OSC_PORT),
+            osc_bind: arg_value(args, "--osc-bind")?.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            osc_target: arg_value(args, "--osc-target")?,
+            headless,
+            frames: arg_value(args, "--frames")?.unwrap_or(600),
+            fps,
+            output: arg_value(args, "--output")?.unwrap_or_else(|| PathBuf::from("frames")),
+            cpu: has_arg(args, "--cpu"),
+        })
+    }
+
+    /// The size of headless frames
+    pub fn headless_size(&self) -> (u32, u32) {
+        let (width, height) = self.size.unwrap_or(DEFAULT_HEADLESS_SIZE);
+        (width as u32, height as u32)
+    }
+
+    pub fn window(&self) -> WindowDescriptor {
+        let (mode, (width, height)) = match (self.windowed, self.size) {
+            (true, size) => (WindowMode::Windowed, size.unwrap_or(DEFAULT_WINDOW_SIZE)),
+            (false, Some(size)) => (WindowMode::SizedFullscreen, size),
+            (false, None) => (WindowMode::BorderlessFullscreen, DEFAULT_WINDOW_SIZE),
+        };
+        WindowDescriptor {
+            mode,
+            width,
+            height,
+            present_mode: self.present_mode,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<LaunchOptions, String> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        LaunchOptions::from_args(&args)
+    }
+
+    #[test]
+    fn every_option_in_usage_parses() {
+        let options = parse(
+            "--windowed --width 800 --height 600 --agents 10 --preset a.preset.ron --seed 7 \
+             --present-mode mailbox --zoom 2 --fixed-delta 0.01 --substeps 2 --midi-port x \
+             --midi-virtual --osc-port 9001 --osc-bind 0.0.0.0 --osc-target 127.0.0.1:9002 \
+             --headless --frames 0 --fps 30 --output out --cpu",
+        )
+        .unwrap();
+        assert_eq!(options.size, Some((800.0, 600.0)));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.zoom, 2.0);
+        assert_eq!(options.frames, 0);
+        assert!(options.headless && options.cpu);
+    }
+
+    #[test]
+    fn unknown_arguments_are_rejected() {
+        assert!(parse("--nope").is_err());
+        assert!(parse("--seed 7 stray").is_err());
+        assert!(parse("-h").is_err());
+    }
+
+    #[test]
+    fn values_can_not_be_options() {
+        assert_eq!(parse("--seed --cpu").unwrap_err(), "--seed needs a value");
+        assert_eq!(
+            parse("--preset --headless").unwrap_err(),
+            "--preset needs a value"
+        );
+        assert!(parse("--zoom").is_err());
+    }
+
+    #[test]
+    fn zoom_must_be_positive() {
+        assert!(parse("--zoom 0").is_err());
+        assert!(parse("--zoom -1").is_err());
+        assert_eq!(parse("--zoom 0.5").unwrap().zoom, 0.5);
+    }
+}
@@ -0,0 +1,550 @@
+//! Compute passes over the trail textures, declared as an ordered chain of render graph nodes.
+//!
+//! Every fullscreen pass reads the front trail texture and writes the back one, after which the
+//! two swap, unless it is built with `swap(false)` or `swap_in`. A pass with `iterations(n)` runs
+//! as n links in a row, each swapping. Step nodes only swap in the states they are added with.
+//! [`ComputePassSchedule`] works out which texture is in front at each step of a frame.
+//!
+//! The passes wait until every one of them has compiled. [`ComputePassesReady`] carries that from
+//! the render world to the main world, which decides per frame whether they run along with the
+//! swaps, so a pass skipped while compiling never leaves the textures swapped the wrong way. A
+//! pipeline that goes missing later, while its shader recompiles after a hot reload, copies the
+//! trail through in place of its pass so the swap still holds.
+//!
+//! Links added through [`ComputePassChain::substeps`] are copied once per substep, and the copies
+//! past `SimSettings::steps` skip the frame. Each copy binds the params of its own substep.
+//!
+//! ```ignore
+//! ComputePassChain::new()
+//!     .substeps(MAX_SUBSTEPS, |chain| {
+//!         chain
+//!             .pass(FullscreenComputePass::new("decay", "shaders/utils.wgsl", "decay"))
+//!             .step_node("game_of_life", GameOfLifeNode::new, &[SimState::Playing])
+//!     })
+//!     .node(CAMERA_DRIVER)
+//!     .build(app);
+//! ```
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::{
+            CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor,
+            ComputePipelineDescriptor, Extent3d, PipelineCache,
+        },
+        renderer::RenderContext,
+        RenderApp, RenderStage,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    params_offset, GameOfLifePipeline, SimSettings, SimState, TrailBindGroups, TrailImages,
+    WORKGROUP_SIZE,
+};
+
+/// A compute shader entry point run over the whole texture with the shared simulation bind group.
+#[derive(Debug, Clone)]
+pub struct FullscreenComputePass {
+    name: &'static str,
+    shader: &'static str,
+    entry_point: &'static str,
+    states: &'static [SimState],
+    /// Runs in a row, each reading what the one before wrote
+    iterations: u32,
+    /// `None` swaps in every state the pass runs in
+    swaps_in: Option<&'static [SimState]>,
+}
+
+impl FullscreenComputePass {
+    /// Runs once per frame over the whole texture while the simulation is playing
+    pub fn new(name: &'static str, shader: &'static str, entry_point: &'static str) -> Self {
+        Self {
+            name,
+            shader,
+            entry_point,
+            states: &[SimState::Playing],
+            iterations: 1,
+            swaps_in: None,
+        }
+    }
+
+    pub fn run_in(mut self, states: &'static [SimState]) -> Self {
+        self.states = states;
+        self
+    }
+
+    /// Runs the pass `iterations` times per step, swapping the trail textures after each run.
+    /// None of the passes in the chain repeat yet, the blur blends against the trail from before
+    /// it so running it twice would not blur twice.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    /// Passes that write somewhere other than `texture_out` leave the trail textures in place
+    pub fn swap(mut self, swap: bool) -> Self {
+        self.swaps_in = if swap { None } else { Some(&[]) };
+        self
+    }
+
+    /// Only swaps in some of the states the pass runs in, in the others the next link keeps
+    /// writing `texture_out` and swaps after it
+    pub fn swap_in(mut self, states: &'static [SimState]) -> Self {
+        self.swaps_in = Some(states);
+        self
+    }
+
+    fn swaps_in(&self, state: SimState) -> bool {
+        self.swaps_in.unwrap_or(self.states).contains(&state)
+    }
+}
+
+/// The pipelines of every pass in the chain, for checking that they have all compiled.
+#[derive(Resource, Default)]
+pub struct ComputePassPipelines(Vec<CachedComputePipelineId>);
+
+impl ComputePassPipelines {
+    pub fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        self.0.iter().all(|id| {
+            matches!(
+                pipeline_cache.get_compute_pipeline_state(*id),
+                CachedPipelineState::Ok(_)
+            )
+        })
+    }
+}
+
+/// Whether every pass had compiled by the end of the last rendered frame, shared by both worlds.
+#[derive(Resource, Clone, Default)]
+pub struct ComputePassesReady(Arc<AtomicBool>);
+
+impl ComputePassesReady {
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs after the pipeline cache has processed its queue for the frame
+fn update_passes_ready(
+    pipelines: Res<ComputePassPipelines>,
+    pipeline_cache: Res<PipelineCache>,
+    ready: Res<ComputePassesReady>,
+) {
+    ready
+        .0
+        .store(pipelines.is_ready(&pipeline_cache), Ordering::Relaxed);
+}
+
+/// Adds a node for one substep to the render graph, under the given name
+type AddStepNode = Arc<dyn Fn(&mut RenderGraph, String, u32) + Send + Sync>;
+
+#[derive(Clone)]
+enum ChainLink {
+    /// A pass in a substep, and which of its iterations
+    Pass(FullscreenComputePass, u32, u32),
+    Node(&'static str),
+    StepNode(&'static str, AddStepNode, &'static [SimState], u32),
+}
+
+impl ChainLink {
+    fn in_substep(self, substep: u32) -> Self {
+        match self {
+            ChainLink::Pass(pass, _, iteration) => ChainLink::Pass(pass, substep, iteration),
+            ChainLink::Node(name) => panic!("node {} can not be repeated for substeps", name),
+            ChainLink::StepNode(name, add, swaps_in, _) => {
+                ChainLink::StepNode(name, add, swaps_in, substep)
+            }
+        }
+    }
+}
+
+/// Substep copies of a link are named `name_1`, `name_2` and so on, and further iterations of a
+/// pass `name#2`, `name_1#2` and so on
+fn node_name(name: &str, substep: u32, iteration: u32) -> String {
+    let mut node = name.to_string();
+    if substep > 0 {
+        node += &format!("_{}", substep);
+    }
+    if iteration > 0 {
+        node += &format!("#{}", iteration + 1);
+    }
+    node
+}
+
+/// A link in the chain by its name, substep and iteration
+type LinkKey = (&'static str, u32, u32);
+
+#[derive(Debug, Clone)]
+struct ScheduledLink {
+    name: &'static str,
+    substep: u32,
+    iteration: u32,
+    /// States the link swaps the trail textures in, empty for nodes that draw in place
+    swaps_in: &'static [SimState],
+}
+
+impl ScheduledLink {
+    fn swaps(&self, settings: &SimSettings) -> bool {
+        self.swaps_in.contains(&settings.state) && self.substep < settings.steps()
+    }
+
+    fn key(&self) -> LinkKey {
+        (self.name, self.substep, self.iteration)
+    }
+}
+
+/// The order of the chain, available in both worlds.
+#[derive(Resource, Debug, Clone)]
+pub struct ComputePassSchedule(Vec<ScheduledLink>);
+
+impl ComputePassSchedule {
+    /// How many times the trail textures swap during a frame with these settings
+    pub fn swaps(&self, settings: &SimSettings) -> usize {
+        self.0.iter().filter(|link| link.swaps(settings)).count()
+    }
+
+    /// The front texture each link sees, given the one that ends up in front after the frame
+    fn fronts(
+        &self,
+        settings: &SimSettings,
+        trail_images: &TrailImages,
+    ) -> HashMap<LinkKey, usize> {
+        let running = trail_images.passes_running;
+        let mut front = trail_images.latest;
+        if running {
+            front ^= self.swaps(settings) % 2;
+        }
+        let mut fronts = HashMap::default();
+        for link in self.0.iter() {
+            fronts.insert(link.key(), front);
+            if running && link.swaps(settings) {
+                front ^= 1;
+            }
+        }
+        fronts
+    }
+}
+
+/// Index into `TrailImages` of the front texture for each node in the chain this frame.
+#[derive(Resource, Default)]
+pub struct TrailFronts(HashMap<LinkKey, usize>);
+
+impl TrailFronts {
+    pub fn get(&self, name: &'static str, substep: u32) -> usize {
+        self.get_iteration(name, substep, 0)
+    }
+
+    fn get_iteration(&self, name: &'static str, substep: u32, iteration: u32) -> usize {
+        self.0
+            .get(&(name, substep, iteration))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn prepare_trail_fronts(
+    schedule: Res<ComputePassSchedule>,
+    settings: Res<SimSettings>,
+    trail_images: Res<TrailImages>,
+    mut fronts: ResMut<TrailFronts>,
+) {
+    fronts.0 = schedule.fronts(&settings, &trail_images);
+}
+
+/// Render graph nodes that run one after another, in the order they are added.
+#[derive(Default)]
+pub struct ComputePassChain {
+    links: Vec<ChainLink>,
+}
+
+impl ComputePassChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pass(mut self, pass: FullscreenComputePass) -> Self {
+        for iteration in 0..pass.iterations {
+            self.links.push(ChainLink::Pass(pass.clone(), 0, iteration));
+        }
+        self
+    }
+
+    /// A node that is already in the render graph, like the camera driver
+    pub fn node(mut self, name: &'static str) -> Self {
+        self.links.push(ChainLink::Node(name));
+        self
+    }
+
+    /// A node the chain adds itself, built by `node` for the substep it runs in. In `swaps_in`
+    /// it writes `texture_out` and the trail textures swap after it, like after a pass.
+    pub fn step_node<T: render_graph::Node>(
+        mut self,
+        name: &'static str,
+        node: fn(u32) -> T,
+        swaps_in: &'static [SimState],
+    ) -> Self {
+        let add: AddStepNode = Arc::new(move |graph: &mut RenderGraph, name, substep| {
+            graph.add_node(name, node(substep));
+        });
+        self.links.push(ChainLink::StepNode(name, add, swaps_in, 0));
+        self
+    }
+
+    /// Repeats the passes and step nodes added by `links` for up to `max` substeps per frame
+    pub fn substeps(mut self, max: u32, links: impl FnOnce(Self) -> Self) -> Self {
+        let links = links(Self::new()).links;
+        for substep in 0..max {
+            self.links
+                .extend(links.iter().cloned().map(|link| link.in_substep(substep)));
+        }
+        self
+    }
+
+    fn schedule(&self) -> ComputePassSchedule {
+        ComputePassSchedule(
+            self.links
+                .iter()
+                .map(|link| match link {
+                    ChainLink::Pass(pass, substep, iteration) => ScheduledLink {
+                        name: pass.name,
+                        substep: *substep,
+                        iteration: *iteration,
+                        swaps_in: pass.swaps_in.unwrap_or(pass.states),
+                    },
+                    ChainLink::Node(name) => ScheduledLink {
+                        name,
+                        substep: 0,
+                        iteration: 0,
+                        swaps_in: &[],
+                    },
+                    ChainLink::StepNode(name, _, swaps_in, substep) => ScheduledLink {
+                        name,
+                        substep: *substep,
+                        iteration: 0,
+                        swaps_in,
+                    },
+                })
+                .collect(),
+        )
+    }
+
+    /// Queues the pass pipelines and adds their nodes and edges to the render graph.
+    ///
+    /// Needs `GameOfLifePipeline` in the render world for its bind group layout.
+    pub fn build(self, app: &mut App) {
+        let schedule = self.schedule();
+        let ready = ComputePassesReady::default();
+        app.insert_resource(schedule.clone())
+            .insert_resource(ready.clone());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(schedule)
+            .insert_resource(ready)
+            .init_resource::<TrailFronts>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_trail_fronts)
+            .add_system_to_stage(RenderStage::Cleanup, update_passes_ready);
+
+        let layout = render_app
+            .world
+            .resource::<GameOfLifePipeline>()
+            .texture_bind_group_layout
+            .clone();
+
+        let mut names = Vec::with_capacity(self.links.len());
+        let mut nodes = Vec::new();
+        let mut step_nodes = Vec::new();
+        // Substep copies of a pass share its pipeline
+        let mut pipelines = HashMap::<&'static str, CachedComputePipelineId>::default();
+        for link in self.links {
+            match link {
+                ChainLink::Pass(pass, substep, iteration) => {
+                    let pipeline = *pipelines.entry(pass.name).or_insert_with(|| {
+                        let shader = render_app.world.resource::<AssetServer>().load(pass.shader);
+                        render_app
+                            .world
+                            .resource_mut::<PipelineCache>()
+                            .queue_compute_pipeline(ComputePipelineDescriptor {
+                                label: Some(Cow::from(pass.name)),
+                                layout: Some(vec![layout.clone()]),
+                                shader,
+                                shader_defs: vec![],
+                                entry_point: Cow::from(pass.entry_point),
+                            })
+                    });
+                    names.push(node_name(pass.name, substep, iteration));
+                    nodes.push(FullscreenComputePassNode {
+                        pass,
+                        pipeline,
+                        substep,
+                        iteration,
+                    });
+                }
+                ChainLink::Node(name) => names.push(name.to_string()),
+                ChainLink::StepNode(name, add, _, substep) => {
+                    names.push(node_name(name, substep, 0));
+                    step_nodes.push((node_name(name, substep, 0), add, substep));
+                }
+            }
+        }
+
+        render_app.insert_resource(ComputePassPipelines(pipelines.into_values().collect()));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        for node in nodes {
+            let name = node_name(node.pass.name, node.substep, node.iteration);
+            render_graph.add_node(name, node);
+        }
+        for (name, add, substep) in step_nodes {
+            add(&mut render_graph, name, substep);
+        }
+        for pair in names.windows(2) {
+            render_graph
+                .add_node_edge(pair[0].clone(), pair[1].clone())
+                .unwrap();
+        }
+    }
+}
+
+struct FullscreenComputePassNode {
+    pass: FullscreenComputePass,
+    pipeline: CachedComputePipelineId,
+    substep: u32,
+    iteration: u32,
+}
+
+impl render_graph::Node for FullscreenComputePassNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let settings = world.resource::<SimSettings>();
+        if !world.resource::<TrailImages>().passes_running
+            || !self.pass.states.contains(&settings.state)
+            || self.substep >= settings.steps()
+        {
+            return Ok(());
+        }
+        let front = world.resource::<TrailFronts>().get_iteration(
+            self.pass.name,
+            self.substep,
+            self.iteration,
+        );
+        let pipeline = match world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(self.pipeline)
+        {
+            Some(pipeline) => pipeline,
+            None => {
+                if self.pass.swaps_in(settings.state) {
+                    copy_trail_through(render_context, world, front);
+                }
+                return Ok(());
+            }
+        };
+        let texture_bind_group = &world.resource::<TrailBindGroups>().0[front];
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_bind_group(0, texture_bind_group, &[params_offset(self.substep)]);
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups(
+            settings.width.div_ceil(WORKGROUP_SIZE),
+            settings.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        Ok(())
+    }
+}
+
+/// Stands in for a pass whose pipeline is missing, the back texture gets the front one as it is
+fn copy_trail_through(render_context: &mut RenderContext, world: &World, front: usize) {
+    let trail_images = world.resource::<TrailImages>();
+    let gpu_images = world.resource::<RenderAssets<Image>>();
+    let (source, target) = match (
+        gpu_images.get(&trail_images.images[front]),
+        gpu_images.get(&trail_images.images[1 - front]),
+    ) {
+        (Some(source), Some(target)) => (source, target),
+        _ => return,
+    };
+
+    render_context.command_encoder.copy_texture_to_texture(
+        source.texture.as_image_copy(),
+        target.texture.as_image_copy(),
+        Extent3d {
+            width: source.size.x as u32,
+            height: source.size.y as u32,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> SimSettings {
+        SimSettings {
+            width: 16,
+            height: 16,
+            randomize: false,
+            state: SimState::Playing,
+            params_change_per_frame: 0.0,
+            seed: 0,
+            fixed_timestep: false,
+            fixed_delta: crate::FIXED_DELTA,
+            locked_frame_time: None,
+            accumulator: 0.0,
+            substeps: 1,
+            time_scale: 1.0,
+            frame_steps: 1,
+            step_delta: crate::FIXED_DELTA,
+        }
+    }
+
+    #[test]
+    fn iterations_swap_after_every_run() {
+        let trail_images = TrailImages {
+            images: [Handle::default(), Handle::default()],
+            latest: 0,
+            passes_running: true,
+        };
+
+        for iterations in [3, 2] {
+            let schedule = ComputePassChain::new()
+                .pass(FullscreenComputePass::new("blur", "", "blur").iterations(iterations))
+                .pass(FullscreenComputePass::new("color", "", "color").swap(false))
+                .schedule();
+            assert_eq!(schedule.swaps(&settings()), iterations as usize);
+
+            // An odd count starts from the other texture, so the last run writes the latest one
+            let fronts = schedule.fronts(&settings(), &trail_images);
+            let first = fronts[&("blur", 0, 0)];
+            assert_eq!(first, iterations as usize % 2);
+            for iteration in 1..iterations {
+                assert_eq!(
+                    fronts[&("blur", 0, iteration)],
+                    first ^ (iteration as usize % 2)
+                );
+            }
+            assert_eq!(fronts[&("color", 0, 0)], trail_images.latest);
+        }
+    }
+}
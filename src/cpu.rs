@@ -0,0 +1,1038 @@
+//! CPU mirror of the kernels in `game_of_life.wgsl` and `utils.wgsl`.
+//!
+//! Every function follows its WGSL counterpart step by step, including the integer wrapping of the
+//! hashes and the half float storage of the trail texture, so it can be compared against the GPU
+//! output and used where no adapter is available.
+use std::f32::consts::PI;
+
+use crate::{spawn_mask::SpawnMaskData, Agent, SimParamsExport, SpeciesExport};
+
+pub fn hash(value: u32) -> u32 {
+    let mut state = value;
+    state ^= 2747636419;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state.wrapping_mul(2654435769)
+}
+
+pub fn random_float(value: u32) -> f32 {
+    hash(value) as f32 / 4294967295.0
+}
+
+/// Stand-in for the `Rgba16Float` trail texture the kernels read and write, and the food map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrailMap {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl TrailMap {
+    pub fn new(width: u32, height: u32, fill: [f32; 4]) -> Self {
+        Self {
+            width,
+            height,
+            texels: vec![fill; (width * height) as usize],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+        Some(y as usize * self.width as usize + x as usize)
+    }
+
+    /// Out of bounds loads read zero, like the robust access wgpu enables
+    pub fn load(&self, x: i32, y: i32) -> [f32; 4] {
+        match self.index(x, y) {
+            Some(index) => self.texels[index],
+            None => [0.0; 4],
+        }
+    }
+
+    /// Out of bounds stores are skipped, stored values are rounded to half float precision
+    pub fn store(&mut self, x: i32, y: i32, color: [f32; 4]) {
+        if let Some(index) = self.index(x, y) {
+            self.texels[index] = color.map(round_to_half);
+        }
+    }
+}
+
+/// Rounds to the nearest value with a 10 bit mantissa, ignoring the range limits of half floats
+/// which the trail never comes near
+fn round_to_half(value: f32) -> f32 {
+    f32::from_bits((value.to_bits() + 0x1000) & !0x1fff)
+}
+
+const BOUNDARY_WRAP: u32 = 1;
+const BOUNDARY_REFLECT: u32 = 2;
+const BOUNDARY_CLAMP: u32 = 3;
+
+/// The texel that is read in place of `(x, y)` when it lies outside the texture
+pub fn boundary_texel(x: i32, y: i32, params: &SimParamsExport) -> (i32, i32) {
+    let axis = |value: i32, size: i32| {
+        let value = match params.boundary_mode {
+            BOUNDARY_WRAP => value.rem_euclid(size),
+            BOUNDARY_REFLECT if value < 0 => -value - 1,
+            BOUNDARY_REFLECT if value >= size => 2 * size - value - 1,
+            _ => value,
+        };
+        value.clamp(0, size - 1)
+    };
+    (axis(x, params.width as i32), axis(y, params.height as i32))
+}
+
+fn dot(a: [f32; 4], b: [f32; 4]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3]
+}
+
+fn max(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [0, 1, 2, 3].map(|channel| a[channel].max(b[channel]))
+}
+
+/// One in the trail channel species `index` deposits into, zero in the others
+pub fn species_channel(index: u32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|channel| if channel == index { 1.0 } else { 0.0 })
+}
+
+/// One in the channels of the species that are simulated
+fn active_channels(params: &SimParamsExport) -> [f32; 4] {
+    [0, 1, 2, 3].map(|channel| {
+        if channel < params.species_count {
+            1.0
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Index of the first mask pixel whose cumulative luminance reaches `value`
+pub fn sample_mask(mask: &SpawnMaskData, value: f32) -> u32 {
+    let mut low = 0;
+    let mut high = mask.width * mask.height - 1;
+    while low < high {
+        let mid = (low + high) / 2;
+        if mask.texels[mid as usize].cdf < value {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+fn mask_luminance(mask: &SpawnMaskData, x: i32, y: i32) -> f32 {
+    let x = x.clamp(0, mask.width as i32 - 1);
+    let y = y.clamp(0, mask.height as i32 - 1);
+    mask.texels[(y * mask.width as i32 + x) as usize].luminance
+}
+
+const HEADING_OUTWARD: u32 = 1;
+const HEADING_INWARD: u32 = 2;
+const HEADING_TANGENT: u32 = 3;
+
+/// Heading relative to the point the spawn shape is built around, random when on top of it
+fn spawn_heading(rule: u32, position: [f32; 2], anchor: [f32; 2], random: u32) -> f32 {
+    let away = [position[0] - anchor[0], position[1] - anchor[1]];
+    if away[0] != 0.0 || away[1] != 0.0 {
+        match rule {
+            HEADING_OUTWARD => return away[1].atan2(away[0]),
+            HEADING_INWARD => return (-away[1]).atan2(-away[0]),
+            HEADING_TANGENT => return away[0].atan2(-away[1]),
+            _ => {}
+        }
+    }
+    random_float(random) * 2.0 * PI
+}
+
+pub fn init(
+    id: u32,
+    agent: &mut Agent,
+    trail: &mut TrailMap,
+    params: &SimParamsExport,
+    species: &[SpeciesExport],
+    mask: &SpawnMaskData,
+) {
+    let random = hash(
+        id.wrapping_mul(params.width)
+            .wrapping_mul(params.salt)
+            .wrapping_add((params.time * 100000.0) as u32),
+    );
+
+    let center = [params.width as f32 / 2.0, params.height as f32 / 2.0];
+
+    let species_index = id % params.species_count;
+    let settings = &species[species_index as usize];
+    agent.species = species_index;
+
+    match settings.mode {
+        0 => {
+            agent.position = center;
+            agent.angle = random_float(random) * 2.0 * PI;
+        }
+        1 => {
+            let theta = random_float(random) * 2.0 * PI;
+            let seed = hash(random.wrapping_add(params.salt));
+            let r = params.height as f32 * 0.25 * random_float(seed).sqrt();
+
+            agent.position = [center[0] + r * theta.sin(), center[1] + r * theta.cos()];
+
+            agent.angle = theta + PI;
+        }
+        2 | 3 if settings.mode == 2 || mask.width == 0 => {
+            agent.position = [
+                random_float(id.wrapping_mul(random).wrapping_add(id)) * params.width as f32,
+                random_float(
+                    id.wrapping_mul(params.delta as u32)
+                        .wrapping_mul(params.salt)
+                        .wrapping_mul(random)
+                        .wrapping_add(id),
+                ) * params.height as f32,
+            ];
+
+            agent.angle = random_float(random) * 2.0 * PI;
+        }
+        3 => {
+            let pixel = sample_mask(mask, random_float(hash(random.wrapping_add(1))));
+            let mask_x = (pixel % mask.width) as i32;
+            let mask_y = (pixel / mask.width) as i32;
+
+            // Fit the mask into the texture, keeping its aspect ratio
+            let size = [params.width as f32, params.height as f32];
+            let mask_size = [mask.width as f32, mask.height as f32];
+            let scale = (size[0] / mask_size[0]).min(size[1] / mask_size[1]);
+            let jitter = [
+                random_float(hash(random.wrapping_add(2))),
+                random_float(hash(random.wrapping_add(3))),
+            ];
+            let mask_pos = [mask_x as f32, mask_y as f32];
+            for axis in 0..2 {
+                let offset = (size[axis] - mask_size[axis] * scale) / 2.0;
+                agent.position[axis] =
+                    (offset + (mask_pos[axis] + jitter[axis]) * scale).min(size[axis] - 0.001);
+            }
+
+            agent.angle = random_float(random) * 2.0 * PI;
+            if settings.follow_mask_gradient != 0 {
+                let gradient = [
+                    mask_luminance(mask, mask_x + 1, mask_y)
+                        - mask_luminance(mask, mask_x - 1, mask_y),
+                    mask_luminance(mask, mask_x, mask_y + 1)
+                        - mask_luminance(mask, mask_x, mask_y - 1),
+                ];
+                if gradient[0] != 0.0 || gradient[1] != 0.0 {
+                    agent.angle = gradient[1].atan2(gradient[0]);
+                }
+            }
+        }
+        4..=8 => {
+            // Shape sizes are relative to half the shorter side of the texture
+            let radius = settings.spawn_radius * center[0].min(center[1]);
+            let count = settings.spawn_count.max(1);
+            let index = hash(random.wrapping_add(1)) % count;
+            let t = random_float(hash(random.wrapping_add(2)));
+            let mut anchor = center;
+            let on_circle = |radius: f32, theta: f32| {
+                [
+                    center[0] + radius * theta.cos(),
+                    center[1] + radius * theta.sin(),
+                ]
+            };
+
+            agent.position = match settings.mode {
+                4 => on_circle(radius * (index + 1) as f32 / count as f32, t * 2.0 * PI),
+                5 => on_circle(
+                    t * radius,
+                    t * 3.0 * 2.0 * PI + index as f32 * 2.0 * PI / count as f32,
+                ),
+                6 => {
+                    anchor = [
+                        random_float(hash((index * 2).wrapping_add(params.salt)))
+                            * params.width as f32,
+                        random_float(hash((index * 2 + 1).wrapping_add(params.salt)))
+                            * params.height as f32,
+                    ];
+                    let distance = radius
+                        * (-2.0
+                            * random_float(hash(random.wrapping_add(3)))
+                                .max(0.000001)
+                                .ln())
+                        .sqrt();
+                    let theta = t * 2.0 * PI;
+                    [
+                        anchor[0] + distance * theta.cos(),
+                        anchor[1] + distance * theta.sin(),
+                    ]
+                }
+                7 => {
+                    let along = (t * 2.0 - 1.0) * settings.spawn_radius;
+                    if settings.spawn_vertical != 0 {
+                        [center[0], center[1] + along * center[1]]
+                    } else {
+                        [center[0] + along * center[0], center[1]]
+                    }
+                }
+                _ => on_circle(radius, t * 2.0 * PI),
+            };
+
+            let rule = if settings.mode == 8 {
+                HEADING_OUTWARD
+            } else {
+                settings.spawn_heading
+            };
+            agent.angle = spawn_heading(rule, agent.position, anchor, random);
+            agent.position[0] = agent.position[0].clamp(0.0, params.width as f32 - 0.001);
+            agent.position[1] = agent.position[1].clamp(0.0, params.height as f32 - 0.001);
+        }
+        _ => {}
+    }
+
+    let (x, y) = (agent.position[0] as i32, agent.position[1] as i32);
+    let deposited = max(trail.load(x, y), species_channel(species_index));
+    trail.store(x, y, deposited);
+}
+
+pub fn sense(
+    agent: &Agent,
+    settings: &SpeciesExport,
+    sensor_angle_spacing: f32,
+    trail: &TrailMap,
+    food: &TrailMap,
+    params: &SimParamsExport,
+) -> f32 {
+    let sensor_angle = agent.angle + sensor_angle_spacing;
+    let sensor_dir = [sensor_angle.cos(), sensor_angle.sin()];
+
+    let sensor_x = (agent.position[0] + sensor_dir[0] * settings.sensor_offset_distance) as i32;
+    let sensor_y = (agent.position[1] + sensor_dir[1] * settings.sensor_offset_distance) as i32;
+
+    let own = species_channel(agent.species);
+    let active = active_channels(params);
+    let size = settings.sensor_size as i32;
+    let mut sum = 0.0;
+    for offset_x in -size..=size {
+        for offset_y in -size..=size {
+            let (sample_x, sample_y) =
+                boundary_texel(sensor_x + offset_x, sensor_y + offset_y, params);
+            let pixel = trail.load(sample_x, sample_y);
+            // Own trails attract and the other species' trails repel
+            sum += 2.0 * dot(pixel, own) - dot(pixel, active);
+            // The food map is greyscale, every species is drawn to it
+            sum += params.food_weight * food.load(sample_x, sample_y)[0];
+        }
+    }
+
+    sum
+}
+
+/// Senses `trail` and deposits into `deposit`, the brushed copy of it the GPU kernel writes as
+/// `texture_out`
+#[allow(clippy::too_many_arguments)]
+pub fn update(
+    id: u32,
+    agent: &mut Agent,
+    trail: &TrailMap,
+    deposit: &mut TrailMap,
+    food: &TrailMap,
+    params: &SimParamsExport,
+    species: &[SpeciesExport],
+) {
+    let settings = &species[agent.species as usize];
+    let location = (agent.position[0] as i32, agent.position[1] as i32);
+
+    let random = hash(
+        id.wrapping_mul(params.width)
+            .wrapping_add(hash((agent.position[0] * agent.position[1]) as u32))
+            .wrapping_add((params.time * 100000.0) as u32),
+    );
+
+    let random_steer_strength = random_float(random);
+    let turn_speed = settings.turn_speed * 2.0 * PI;
+
+    // Steer based on sensory data
+    let sensor_angle_rad = settings.sensor_angle_spacing * (PI / 180.0);
+    let weight_forward = sense(agent, settings, 0.0, trail, food, params);
+    let weight_left = sense(agent, settings, sensor_angle_rad, trail, food, params);
+    let weight_right = sense(agent, settings, -sensor_angle_rad, trail, food, params);
+
+    if weight_forward > weight_left && weight_forward > weight_right {
+        // Continue in same direction
+    } else if weight_forward < weight_left && weight_forward < weight_right {
+        agent.angle += (random_steer_strength - 0.5) * 2.0 * turn_speed * params.delta;
+    } else if weight_right > weight_left {
+        agent.angle -= random_steer_strength * turn_speed * params.delta;
+    } else if weight_left > weight_right {
+        agent.angle += random_steer_strength * turn_speed * params.delta;
+    }
+
+    let mut new_pos = [
+        agent.position[0] + agent.angle.cos() * settings.move_speed * params.delta,
+        agent.position[1] + agent.angle.sin() * settings.move_speed * params.delta,
+    ];
+
+    // The repel brush pushes agents out and turns them away from the cursor
+    let away = [
+        agent.position[0] - params.brush_x,
+        agent.position[1] - params.brush_y,
+    ];
+    let brush_distance = away[0].hypot(away[1]);
+    if params.brush_tool == BRUSH_REPEL
+        && brush_distance < params.brush_radius
+        && brush_distance > 0.0
+    {
+        let falloff = 1.0 - brush_distance / params.brush_radius;
+        agent.angle = away[1].atan2(away[0]);
+        for axis in 0..2 {
+            new_pos[axis] += away[axis] / brush_distance
+                * params.brush_strength
+                * params.brush_radius
+                * falloff
+                * params.delta;
+        }
+    }
+
+    let size = [params.width as f32, params.height as f32];
+    // Keeps positions that land exactly on the far edge inside the texture
+    let max_pos = [size[0] - 0.001, size[1] - 0.001];
+    let outside = |pos: f32, size: f32| pos < 0.0 || pos >= size;
+
+    if outside(new_pos[0], size[0]) || outside(new_pos[1], size[1]) {
+        match params.boundary_mode {
+            BOUNDARY_WRAP => {
+                for axis in 0..2 {
+                    new_pos[axis] = (new_pos[axis]
+                        - (new_pos[axis] / size[axis]).floor() * size[axis])
+                        .min(max_pos[axis]);
+                }
+            }
+            BOUNDARY_REFLECT => {
+                if outside(new_pos[0], size[0]) {
+                    agent.angle = PI - agent.angle;
+                }
+                if outside(new_pos[1], size[1]) {
+                    agent.angle = -agent.angle;
+                }
+                for axis in 0..2 {
+                    if new_pos[axis] < 0.0 {
+                        new_pos[axis] = -new_pos[axis];
+                    }
+                    if new_pos[axis] >= size[axis] {
+                        new_pos[axis] = 2.0 * size[axis] - new_pos[axis];
+                    }
+                    new_pos[axis] = new_pos[axis].clamp(0.0, max_pos[axis]);
+                }
+            }
+            // Keeps the heading, so the agent slides along the edge
+            BOUNDARY_CLAMP => {
+                for axis in 0..2 {
+                    new_pos[axis] = new_pos[axis].clamp(0.0, max_pos[axis]);
+                }
+            }
+            _ => {
+                agent.angle = random_float(random) * 2.0 * PI;
+                new_pos = [
+                    agent.position[0] + agent.angle.cos() * settings.move_speed * params.delta,
+                    agent.position[1] + agent.angle.sin() * settings.move_speed * params.delta,
+                ];
+            }
+        }
+    }
+
+    agent.position = new_pos;
+
+    // Keeps the deposits of other species on the same texel
+    let deposited = max(
+        deposit.load(location.0, location.1),
+        species_channel(agent.species),
+    );
+    deposit.store(location.0, location.1, deposited);
+}
+
+/// One dimension of the gaussian, `direction` is (1, 0) or (0, 1)
+pub fn gaussian_blur(
+    source: &TrailMap,
+    x: i32,
+    y: i32,
+    direction: (i32, i32),
+    params: &SimParamsExport,
+) -> [f32; 4] {
+    let radius = params.blur_radius as i32;
+    let two_sigma_sq = 2.0 * (params.blur_sigma * params.blur_sigma).max(0.0001);
+
+    let mut sum = [0.0; 4];
+    let mut total_weight = 0.0;
+    for offset in -radius..=radius {
+        let weight = (-((offset * offset) as f32) / two_sigma_sq).exp();
+        let (sample_x, sample_y) =
+            boundary_texel(x + direction.0 * offset, y + direction.1 * offset, params);
+        let sample = source.load(sample_x, sample_y);
+        for channel in 0..4 {
+            sum[channel] += sample[channel] * weight;
+        }
+        total_weight += weight;
+    }
+
+    sum.map(|c| c / total_weight)
+}
+
+/// The two blur passes, each reading an untouched copy of the trail like the GPU kernels read the
+/// front texture. The vertical pass blends the blur into the trail from before the horizontal one
+/// by `trail_weight`, so the weight applies once.
+pub fn blur(trail: &mut TrailMap, params: &SimParamsExport) {
+    let original = trail.clone();
+    let mut horizontal = trail.clone();
+    for y in 0..params.height as i32 {
+        for x in 0..params.width as i32 {
+            horizontal.store(x, y, gaussian_blur(&original, x, y, (1, 0), params));
+        }
+    }
+
+    for y in 0..params.height as i32 {
+        for x in 0..params.width as i32 {
+            let original = original.load(x, y);
+            let blurred = gaussian_blur(&horizontal, x, y, (0, 1), params);
+            let mut color = [0.0; 4];
+            for channel in 0..4 {
+                color[channel] = original[channel]
+                    + (blurred[channel] - original[channel]) * params.trail_weight;
+            }
+            trail.store(x, y, color);
+        }
+    }
+}
+
+const BRUSH_DEPOSIT: u32 = 1;
+const BRUSH_ERASE: u32 = 2;
+const BRUSH_REPEL: u32 = 3;
+
+pub fn brush(trail: &mut TrailMap, params: &SimParamsExport) {
+    let channel = species_channel(params.brush_species);
+    for y in 0..params.height as i32 {
+        for x in 0..params.width as i32 {
+            let mut color = trail.load(x, y);
+            let distance = (x as f32 + 0.5 - params.brush_x).hypot(y as f32 + 0.5 - params.brush_y);
+            if distance < params.brush_radius {
+                let falloff = 1.0 - distance / params.brush_radius;
+                let amount = (params.brush_strength * params.delta * falloff).clamp(0.0, 1.0);
+                let target = match params.brush_tool {
+                    BRUSH_DEPOSIT => Some(max(color, channel)),
+                    BRUSH_ERASE => Some([0.0; 4]),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    for channel in 0..4 {
+                        color[channel] += (target[channel] - color[channel]) * amount;
+                    }
+                }
+            }
+            trail.store(x, y, color);
+        }
+    }
+}
+
+pub fn decay(trail: &mut TrailMap, params: &SimParamsExport) {
+    let amount = params.decay_rate * params.delta;
+    for y in 0..params.height as i32 {
+        for x in 0..params.width as i32 {
+            let color = trail.load(x, y);
+            trail.store(x, y, color.map(|c| (c - amount).max(0.0)));
+        }
+    }
+}
+
+/// The `color` pass, `Rgba8Unorm` texels of the display texture. `palette_lut` holds the
+/// `Rgba8Unorm` texels of the palette lookup texture, only read with `palette_enabled`.
+pub fn color(
+    trail: &TrailMap,
+    params: &SimParamsExport,
+    species: &[SpeciesExport],
+    palette_lut: &[u8],
+) -> Vec<u8> {
+    let lut_size = (palette_lut.len() / 4) as i32;
+    let lut = |index: i32| {
+        let texel = &palette_lut[index as usize * 4..][..4];
+        [0, 1, 2].map(|channel| texel[channel] as f32 / 255.0)
+    };
+
+    let mut display = Vec::with_capacity((params.width * params.height * 4) as usize);
+    for y in 0..params.height as i32 {
+        for x in 0..params.width as i32 {
+            let trail = trail.load(x, y);
+            let color = if params.palette_enabled == 0 {
+                // Every species in its color, tinted toward the blur mask as its trail fades
+                let mut color = [0.0; 3];
+                for (index, settings) in species[..params.species_count as usize].iter().enumerate()
+                {
+                    let intensity = trail[index].clamp(0.0, 1.0);
+                    for (channel, color) in color.iter_mut().enumerate() {
+                        let tint = params.blur_mask[channel]
+                            + (1.0 - params.blur_mask[channel]) * intensity;
+                        *color += intensity * settings.color[channel] * tint;
+                    }
+                }
+                color
+            } else {
+                // The strongest trail on the texel, the palette does not tell species apart
+                let active = active_channels(params);
+                let intensity = (0..4)
+                    .map(|channel| trail[channel] * active[channel])
+                    .fold(0.0, f32::max)
+                    .clamp(0.0, 1.0);
+                let position = intensity * (lut_size - 1) as f32;
+                let low = position.floor() as i32;
+                let high = (low + 1).min(lut_size - 1);
+                let (low_color, high_color) = (lut(low), lut(high));
+                [0, 1, 2].map(|channel| {
+                    low_color[channel]
+                        + (high_color[channel] - low_color[channel]) * position.fract()
+                })
+            };
+            display.extend(color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+            display.push(255);
+        }
+    }
+    display
+}
+
+/// Runs the whole simulation on plain buffers, in the order the render graph dispatches it.
+pub struct CpuSimulation {
+    pub params: SimParamsExport,
+    pub species: Vec<SpeciesExport>,
+    pub agents: Vec<Agent>,
+    pub trail: TrailMap,
+    /// Greyscale food in the red channel, empty by default
+    pub food: TrailMap,
+    pub mask: SpawnMaskData,
+}
+
+impl CpuSimulation {
+    pub fn new(params: SimParamsExport, species: Vec<SpeciesExport>, num_agents: u32) -> Self {
+        Self {
+            agents: vec![
+                Agent {
+                    position: [0.0; 2],
+                    angle: 0.0,
+                    species: 0,
+                };
+                num_agents as usize
+            ],
+            trail: TrailMap::new(params.width, params.height, [0.0; 4]),
+            food: TrailMap::new(params.width, params.height, [0.0; 4]),
+            params,
+            species,
+            mask: SpawnMaskData::default(),
+        }
+    }
+
+    pub fn init(&mut self) {
+        for (id, agent) in self.agents.iter_mut().enumerate() {
+            init(
+                id as u32,
+                agent,
+                &mut self.trail,
+                &self.params,
+                &self.species,
+                &self.mask,
+            );
+        }
+    }
+
+    pub fn step(&mut self) {
+        decay(&mut self.trail, &self.params);
+        blur(&mut self.trail, &self.params);
+        let sensed = self.trail.clone();
+        brush(&mut self.trail, &self.params);
+        for (id, agent) in self.agents.iter_mut().enumerate() {
+            update(
+                id as u32,
+                agent,
+                &sensed,
+                &mut self.trail,
+                &self.food,
+                &self.params,
+                &self.species,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(width: u32, height: u32) -> SimParamsExport {
+        SimParamsExport {
+            blur_mask: [1.0; 4],
+            width,
+            height,
+            species_count: 1,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+
+    fn species() -> SpeciesExport {
+        SpeciesExport {
+            color: [1.0, 0.5, 0.25, 1.0],
+            move_speed: 10.0,
+            turn_speed: 1.0,
+            sensor_angle_spacing: 90.0,
+            sensor_offset_distance: 3.0,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+
+    fn agent(x: f32, y: f32, angle: f32) -> Agent {
+        Agent {
+            position: [x, y],
+            angle,
+            species: 0,
+        }
+    }
+
+    fn assert_texel(actual: [f32; 4], expected: [f32; 4]) {
+        for channel in 0..4 {
+            assert!(
+                (actual[channel] - expected[channel]).abs() < 0.001,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn hash_matches_wgsl() {
+        assert_eq!(hash(0), 1739749167);
+        assert_eq!(hash(1), 150776505);
+        assert_eq!(hash(12345), 3826328255);
+        assert_eq!(hash(u32::MAX), 3360068443);
+        assert!((random_float(0) - 0.405_066_9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stores_round_to_half_floats() {
+        let mut trail = TrailMap::new(1, 1, [0.0; 4]);
+        trail.store(0, 0, [0.2, 0.7, 1.0 / 3.0, 1.0]);
+        assert_eq!(
+            trail.load(0, 0),
+            [0.199_951_17, 0.700_195_3, 0.333_251_95, 1.0]
+        );
+        // Out of bounds stores are dropped and loads read zero
+        trail.store(1, 0, [1.0; 4]);
+        assert_eq!(trail.load(1, 0), [0.0; 4]);
+    }
+
+    #[test]
+    fn boundary_texel_modes() {
+        let mut params = params(4, 3);
+        for mode in 0..=BOUNDARY_CLAMP {
+            params.boundary_mode = mode;
+            assert_eq!(boundary_texel(2, 1, &params), (2, 1));
+        }
+
+        params.boundary_mode = 0;
+        assert_eq!(boundary_texel(-1, 5, &params), (0, 2));
+        params.boundary_mode = BOUNDARY_CLAMP;
+        assert_eq!(boundary_texel(-1, 5, &params), (0, 2));
+        params.boundary_mode = BOUNDARY_WRAP;
+        assert_eq!(boundary_texel(-1, 3, &params), (3, 0));
+        assert_eq!(boundary_texel(5, -4, &params), (1, 2));
+        params.boundary_mode = BOUNDARY_REFLECT;
+        assert_eq!(boundary_texel(-1, 3, &params), (0, 2));
+        assert_eq!(boundary_texel(-3, 4, &params), (2, 1));
+    }
+
+    #[test]
+    fn decay_subtracts_rate_times_delta() {
+        let mut params = params(2, 1);
+        params.decay_rate = 3.0;
+        params.delta = 0.1;
+        let mut trail = TrailMap::new(2, 1, [0.5, 0.25, 0.0, 1.0]);
+        decay(&mut trail, &params);
+        assert_texel(trail.load(0, 0), [0.2, 0.0, 0.0, 0.7]);
+        assert_texel(trail.load(1, 0), [0.2, 0.0, 0.0, 0.7]);
+    }
+
+    #[test]
+    fn gaussian_blur_weights() {
+        let mut params = params(5, 1);
+        params.blur_radius = 1;
+        params.blur_sigma = 1.0;
+        let mut trail = TrailMap::new(5, 1, [0.0; 4]);
+        trail.store(2, 0, [1.0; 4]);
+
+        // The center weighs 1 and its neighbours exp(-0.5)
+        let center = gaussian_blur(&trail, 2, 0, (1, 0), &params);
+        let side = gaussian_blur(&trail, 1, 0, (1, 0), &params);
+        assert_texel(center, [0.451_862_8; 4]);
+        assert_texel(side, [0.274_068_6; 4]);
+        assert_texel(gaussian_blur(&trail, 2, 0, (0, 1), &params), [1.0; 4]);
+    }
+
+    #[test]
+    fn blur_leaves_uniform_trails_alone() {
+        let mut params = params(3, 3);
+        params.blur_radius = 1;
+        params.blur_sigma = 1.0;
+        // The mask only tints the display, it does not touch the trail
+        params.blur_mask = [1.0, 0.5, 0.0, 1.0];
+        let uniform = TrailMap::new(3, 3, [0.5, 0.5, 0.5, 1.0]);
+
+        for trail_weight in [0.0, 0.5, 1.0] {
+            params.trail_weight = trail_weight;
+            let mut trail = uniform.clone();
+            blur(&mut trail, &params);
+            assert_eq!(trail, uniform);
+        }
+    }
+
+    #[test]
+    fn trail_ownership_survives_the_default_blur_mask() {
+        let mut params = params(16, 16);
+        params.species_count = 3;
+        params.blur_radius = 1;
+        params.blur_sigma = 1.0;
+        params.trail_weight = 1.0;
+        // The default mask, which takes out blue and dims green
+        params.blur_mask = [1.0, 240.0 / 255.0, 0.0, 1.0];
+        let food = TrailMap::new(16, 16, [0.0; 4]);
+        // A blue species between two of the same color
+        let colors = [[1.0; 4], [0.0, 0.0, 1.0, 1.0], [1.0; 4]];
+
+        for owner in 0..3 {
+            let mut trail = TrailMap::new(16, 16, [0.0; 4]);
+            trail.store(11, 8, species_channel(owner));
+            blur(&mut trail, &params);
+
+            for (index, color) in colors.iter().enumerate() {
+                let settings = SpeciesExport {
+                    color: *color,
+                    ..species()
+                };
+                let sensing = Agent {
+                    species: index as u32,
+                    ..agent(8.5, 8.5, 0.0)
+                };
+                let weight = sense(&sensing, &settings, 0.0, &trail, &food, &params);
+                if index as u32 == owner {
+                    assert!(weight > 0.0, "species {} avoids its own trail", index);
+                } else {
+                    assert!(weight < 0.0, "species {} follows species {}", index, owner);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blur_blends_the_trail_weight_once() {
+        let mut params = params(5, 5);
+        params.blur_radius = 1;
+        params.blur_sigma = 1.0;
+        params.blur_mask = [1.0; 4];
+        params.trail_weight = 0.5;
+        let mut trail = TrailMap::new(5, 5, [0.0; 4]);
+        trail.store(2, 2, [1.0; 4]);
+
+        // Half of the original texel plus half of the 2D gaussian of 1 and exp(-0.5) weights
+        blur(&mut trail, &params);
+        assert_texel(trail.load(2, 2), [0.602_09; 4]);
+        assert_texel(trail.load(2, 1), [0.061_92; 4]);
+        assert_texel(trail.load(1, 1), [0.037_56; 4]);
+        assert_texel(trail.load(0, 0), [0.0; 4]);
+    }
+
+    #[test]
+    fn color_is_opaque_with_and_without_palette() {
+        let mut params = params(2, 1);
+        params.species_count = 2;
+        let species = [
+            species(),
+            SpeciesExport {
+                color: [0.0, 0.0, 1.0, 1.0],
+                ..species()
+            },
+        ];
+        let mut trail = TrailMap::new(2, 1, [0.0; 4]);
+        trail.store(0, 0, [0.5, 0.25, 0.0, 0.0]);
+        // Channels of species that are not simulated are not shown
+        trail.store(1, 0, [0.0, 1.5, 0.0, 1.0]);
+        let lut = [0, 0, 0, 255, 255, 128, 0, 255];
+
+        assert_eq!(
+            color(&trail, &params, &species, &lut),
+            [128, 64, 96, 255, 0, 0, 255, 255]
+        );
+
+        // Fading trails are tinted toward the mask
+        params.blur_mask = [1.0, 0.0, 0.0, 1.0];
+        assert_eq!(
+            color(&trail, &params, &species, &lut),
+            [128, 32, 32, 255, 0, 0, 255, 255]
+        );
+
+        // Halfway between the two texels, and clamped to the last one
+        params.palette_enabled = 1;
+        assert_eq!(
+            color(&trail, &params, &species, &lut),
+            [128, 64, 0, 255, 255, 128, 0, 255]
+        );
+    }
+
+    #[test]
+    fn init_spawns_and_deposits() {
+        let mut params = params(16, 16);
+        params.salt = 3;
+        params.species_count = 2;
+        let species = [species(), species()];
+        let mut trail = TrailMap::new(16, 16, [0.0; 4]);
+        let mut agent = agent(0.0, 0.0, 0.0);
+
+        init(
+            1,
+            &mut agent,
+            &mut trail,
+            &params,
+            &species,
+            &SpawnMaskData::default(),
+        );
+        assert_eq!(agent.species, 1);
+        assert_eq!(agent.position, [8.0, 8.0]);
+        // hash(hash(1 * 16 * 3)) mapped onto a full turn
+        assert!((agent.angle - 1.103_999).abs() < 1e-5);
+        assert_eq!(trail.load(8, 8), species_channel(1));
+        assert_eq!(
+            trail.texels.iter().filter(|texel| texel[1] > 0.0).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn update_moves_and_deposits() {
+        let mut params = params(16, 16);
+        params.delta = 0.1;
+        let species = [species()];
+        let food = TrailMap::new(16, 16, [0.0; 4]);
+        let trail = TrailMap::new(16, 16, [0.0; 4]);
+        let mut deposit = trail.clone();
+
+        // Nothing to sense, the agent keeps its heading
+        let mut moved = agent(4.5, 4.5, 0.0);
+        update(
+            0,
+            &mut moved,
+            &trail,
+            &mut deposit,
+            &food,
+            &params,
+            &species,
+        );
+        assert_eq!(moved.angle, 0.0);
+        assert_eq!(moved.position, [5.5, 4.5]);
+        // The trail is left where the agent was
+        assert_eq!(deposit.load(4, 4), species_channel(0));
+
+        // Its own trail under the left sensor turns it left
+        let mut trail = TrailMap::new(16, 16, [0.0; 4]);
+        trail.store(8, 11, species_channel(0));
+        let mut turned = agent(8.5, 8.5, 0.0);
+        update(
+            0,
+            &mut turned,
+            &trail,
+            &mut deposit,
+            &food,
+            &params,
+            &species,
+        );
+        assert!(turned.angle > 0.0);
+
+        // Another species' trail there turns it right
+        params.species_count = 2;
+        let mut trail = TrailMap::new(16, 16, [0.0; 4]);
+        trail.store(8, 11, species_channel(1));
+        let mut turned = agent(8.5, 8.5, 0.0);
+        update(
+            0,
+            &mut turned,
+            &trail,
+            &mut deposit,
+            &food,
+            &params,
+            &[species[0], species[0]],
+        );
+        assert!(turned.angle < 0.0);
+    }
+
+    #[test]
+    fn update_senses_only_the_previous_step() {
+        let mut params = params(16, 16);
+        params.delta = 0.1;
+        let species = [species()];
+        let food = TrailMap::new(16, 16, [0.0; 4]);
+        let trail = TrailMap::new(16, 16, [0.0; 4]);
+        let mut deposit = trail.clone();
+
+        // The first agent deposits under the second one's left sensor, which does not see it
+        let mut first = agent(8.5, 11.5, 0.0);
+        update(
+            0,
+            &mut first,
+            &trail,
+            &mut deposit,
+            &food,
+            &params,
+            &species,
+        );
+        assert_eq!(deposit.load(8, 11), species_channel(0));
+        let mut second = agent(8.5, 8.5, 0.0);
+        update(
+            1,
+            &mut second,
+            &trail,
+            &mut deposit,
+            &food,
+            &params,
+            &species,
+        );
+        assert_eq!(second.angle, 0.0);
+    }
+
+    #[test]
+    fn update_boundary_modes() {
+        let mut params = params(16, 16);
+        params.delta = 0.1;
+        let species = [species()];
+        let food = TrailMap::new(16, 16, [0.0; 4]);
+        let trail = TrailMap::new(16, 16, [0.0; 4]);
+        let mut deposit = trail.clone();
+        let mut step = |mode| {
+            params.boundary_mode = mode;
+            let mut agent = agent(15.5, 4.5, 0.0);
+            update(
+                0,
+                &mut agent,
+                &trail,
+                &mut deposit,
+                &food,
+                &params,
+                &species,
+            );
+            agent
+        };
+
+        let wrapped = step(BOUNDARY_WRAP);
+        assert!((wrapped.position[0] - 0.5).abs() < 1e-4);
+        let reflected = step(BOUNDARY_REFLECT);
+        assert_eq!(reflected.angle, PI);
+        assert!((reflected.position[0] - 15.5).abs() < 1e-4);
+        let clamped = step(BOUNDARY_CLAMP);
+        assert_eq!(clamped.angle, 0.0);
+        assert_eq!(clamped.position[0], 15.999);
+        // Without a boundary the agent picks a new random heading
+        let turned = step(0);
+        assert_ne!(turned.angle, 0.0);
+    }
+}
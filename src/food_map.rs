@@ -0,0 +1,115 @@
+//! A static food map in `texture_second` that agents sense alongside the trails.
+//!
+//! Bright parts of the image attract every species by `SimParams::food_weight`, so the agents grow
+//! transport networks between them. The image is fitted into the simulation keeping its aspect
+//! ratio, and stored as greyscale with the food in every color channel.
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+
+use crate::GameOfLifeImageSecond;
+
+/// The image the food map is built from, `path` is relative to `assets`
+#[derive(Resource, Default)]
+pub struct FoodMap {
+    pub path: String,
+    pub handle: Option<Handle<Image>>,
+}
+
+impl FoodMap {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &str) {
+        self.handle = Some(asset_server.load(path));
+        self.path = path.to_string();
+    }
+
+    pub fn clear(&mut self) {
+        self.handle = None;
+        self.path.clear();
+    }
+}
+
+/// `Rgba8Unorm` texels of `image` fitted into `width` by `height`, luminance weighted by alpha.
+///
+/// Returns `None` for formats Bevy can not convert.
+pub fn fit_food(image: &Image, width: u32, height: u32) -> Option<Vec<u8>> {
+    let luma_alpha = image.convert(TextureFormat::Rg8Unorm)?;
+    let size = luma_alpha.texture_descriptor.size;
+    if size.width == 0 || size.height == 0 {
+        return None;
+    }
+
+    let scale = (width as f32 / size.width as f32).min(height as f32 / size.height as f32);
+    let offset_x = (width as f32 - size.width as f32 * scale) / 2.0;
+    let offset_y = (height as f32 - size.height as f32 * scale) / 2.0;
+
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let image_x = ((x as f32 + 0.5 - offset_x) / scale).floor();
+            let image_y = ((y as f32 + 0.5 - offset_y) / scale).floor();
+            let inside = image_x >= 0.0
+                && image_y >= 0.0
+                && image_x < size.width as f32
+                && image_y < size.height as f32;
+            let food = if inside {
+                let index = (image_y as usize * size.width as usize + image_x as usize) * 2;
+                let pixel = &luma_alpha.data[index..index + 2];
+                (pixel[0] as u16 * pixel[1] as u16 / 255) as u8
+            } else {
+                0
+            };
+            data.extend_from_slice(&[food, food, food, 255]);
+        }
+    }
+    Some(data)
+}
+
+pub struct FoodMapPlugin;
+
+impl Plugin for FoodMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FoodMap>().add_system(update_food_map);
+    }
+}
+
+/// Writes the food into `texture_second` once the image has (re)loaded, or clears it
+fn update_food_map(
+    mut events: EventReader<AssetEvent<Image>>,
+    food_map: Res<FoodMap>,
+    image_second: Res<GameOfLifeImageSecond>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let mut loaded = food_map.is_changed();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                loaded |= Some(handle) == food_map.handle.as_ref()
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    if !loaded {
+        return;
+    }
+    let size = match images.get(&image_second) {
+        Some(image) => image.texture_descriptor.size,
+        None => return,
+    };
+    let data = match &food_map.handle {
+        Some(handle) => match images.get(handle) {
+            Some(image) => match fit_food(image, size.width, size.height) {
+                Some(data) => data,
+                None => {
+                    warn!("{} can not be used as a food map", food_map.path);
+                    return;
+                }
+            },
+            // Still loading
+            None => return,
+        },
+        None => [0, 0, 0, 255].repeat((size.width * size.height) as usize),
+    };
+
+    if let Some(image) = images.get_mut(&image_second) {
+        image.data = data;
+    }
+}
@@ -0,0 +1,177 @@
+//! Offscreen rendering that writes every simulated frame to a numbered PNG.
+//!
+//! Runs the GPU compute graph without a window. When no adapter can be found, or with `--cpu`, the
+//! frames come from [`crate::cpu::CpuSimulation`] instead, started from the same preset.
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    app::AppExit,
+    asset::FileAssetIo,
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageType},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    cli::LaunchOptions,
+    cpu::{self, CpuSimulation},
+    food_map::fit_food,
+    preset::Preset,
+    readback::CapturedFrames,
+    spawn_mask::SpawnMaskData,
+    SimParams, SimSettings, SimState,
+};
+
+#[derive(Debug, Clone, Resource)]
+pub struct HeadlessSettings {
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub fps: f32,
+    pub output: PathBuf,
+    pub cpu: bool,
+}
+
+impl HeadlessSettings {
+    pub fn from_options(options: &LaunchOptions) -> Option<Self> {
+        if !options.headless {
+            return None;
+        }
+        let (width, height) = options.headless_size();
+        Some(Self {
+            width,
+            height,
+            frames: options.frames,
+            fps: options.fps,
+            output: options.output.clone(),
+            cpu: options.cpu,
+        })
+    }
+
+    pub fn frame_delta(&self) -> f32 {
+        1.0 / self.fps
+    }
+
+    fn frame_path(&self, frame: u32) -> PathBuf {
+        self.output.join(format!("frame_{:05}.png", frame))
+    }
+}
+
+pub fn adapter_available() -> bool {
+    let backends = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
+    let instance = wgpu::Instance::new(backends);
+    futures_lite::future::block_on(
+        instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+    )
+    .is_some()
+}
+
+pub fn save_png(path: &Path, width: u32, height: u32, data: &[u8]) -> Result<(), String> {
+    image::save_buffer(path, data, width, height, image::ColorType::Rgba8)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+/// Saves the frames read back from the GPU, the first one only confirms that `init` ran.
+///
+/// Headless runs have no logger, so a frame that can not be written stops the run with the error
+/// on stderr and a failing exit code.
+pub fn save_frames(
+    headless: Res<HeadlessSettings>,
+    frames: Res<CapturedFrames>,
+    mut sim_settings: ResMut<SimSettings>,
+    mut saved: Local<u32>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for frame in frames.try_iter() {
+        if let SimState::Initialize = sim_settings.state {
+            sim_settings.state = SimState::Playing;
+        } else if *saved < headless.frames {
+            let path = headless.frame_path(*saved);
+            if let Err(err) = save_png(&path, frame.width, frame.height, &frame.data) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            *saved += 1;
+        }
+
+        if *saved >= headless.frames {
+            exit.send(AppExit);
+            return;
+        }
+    }
+}
+
+/// Spawns from the same launch options and preset as the GPU path, the preset's spawn mask and
+/// food map included, and writes what the `color` pass would show.
+///
+/// There is no logger without the app, so anything that would only log an error stops the run
+/// instead and is returned.
+pub fn run_cpu(headless: &HeadlessSettings, options: &LaunchOptions) -> Result<(), String> {
+    let preset =
+        Preset::read(&options.preset).map_err(|err| format!("Failed to read preset {}", err))?;
+
+    let mut sim_params = SimParams {
+        width: headless.width,
+        height: headless.height,
+        ..preset.params
+    };
+    sim_params.delta = headless.frame_delta();
+    // A seed in the preset replaces the launch one, like applying the preset does
+    let seed = preset.seed.or(options.seed).unwrap_or_else(rand::random);
+    // Same sequence of salts as the GPU path gets from `SimClock`
+    let mut rng = StdRng::seed_from_u64(seed as u64);
+    sim_params.salt = rng.gen();
+
+    let mut sim = CpuSimulation::new(
+        sim_params.export(),
+        sim_params.export_species().to_vec(),
+        options.agents,
+    );
+    let palette_lut = preset.palette.clone().unwrap_or_default().to_lut();
+
+    if let Some(path) = &preset.spawn_mask {
+        let image = read_image(path).map_err(|err| format!("Failed to read spawn mask {}", err))?;
+        sim.mask = SpawnMaskData::from_image(&image)
+            .ok_or_else(|| format!("Spawn mask {} has no bright pixels", path))?;
+    }
+    if let Some(path) = &preset.food_map {
+        let image = read_image(path).map_err(|err| format!("Failed to read food map {}", err))?;
+        let food = fit_food(&image, headless.width, headless.height)
+            .ok_or_else(|| format!("Food map {} has an unsupported format", path))?;
+        sim.food.texels = food
+            .chunks_exact(4)
+            .map(|texel| [0, 1, 2, 3].map(|channel| texel[channel] as f32 / 255.0))
+            .collect();
+    }
+    sim.init();
+
+    for frame in 0..headless.frames {
+        sim.params.time += sim.params.delta;
+        sim.params.salt = rng.gen();
+        sim.step();
+        save_png(
+            &headless.frame_path(frame),
+            headless.width,
+            headless.height,
+            &cpu::color(&sim.trail, &sim.params, &sim.species, &palette_lut),
+        )?;
+    }
+    Ok(())
+}
+
+/// An image under `assets`, decoded like the asset server would
+fn read_image(path: &str) -> Result<Image, String> {
+    let file = FileAssetIo::get_base_path().join("assets").join(path);
+    let bytes = std::fs::read(&file).map_err(|err| format!("{}: {}", file.display(), err))?;
+    let extension = file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        true,
+    )
+    .map_err(|err| format!("{}: {}", file.display(), err))
+}
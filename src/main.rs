@@ -2,71 +2,193 @@
 //!
 //! Compute shaders use the GPU for computing arbitrary information, that may be independent of what
 //! is rendered to the screen.
-mod blur;
-mod color;
-mod decay;
+mod audio;
+mod brush;
+mod cli;
+mod compute_pass;
+mod cpu;
+mod food_map;
+mod headless;
+mod midi;
+mod osc;
+mod palette;
+mod param_target;
+mod preset;
+mod readback;
+mod record;
+mod resize;
+mod screenshot;
+mod spawn_mask;
+mod timeline;
 
 use bevy::{
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_asset::RenderAssets,
-        render_graph::{self, RenderGraph},
+        render_graph,
         render_resource::*,
         renderer::{RenderContext, RenderDevice, RenderQueue},
         RenderApp, RenderStage,
     },
-    window::WindowDescriptor,
+    winit::WinitPlugin,
 };
 use bevy_egui::{
-    egui::{color_picker::color_edit_button_srgba, Button, Checkbox, Color32, ComboBox, Slider},
+    egui::{
+        color_picker::color_edit_button_srgba, Button, Checkbox, Color32, ComboBox, DragValue,
+        Slider, Ui,
+    },
     EguiContext, EguiPlugin,
 };
-// use bevy_midi::{Midi, MidiRawData, MidiSettings};
-use std::{borrow::Cow, ops::RangeInclusive};
+use brush::{Brush, BrushStroke, BrushTool};
+use cli::LaunchOptions;
+use compute_pass::{
+    ComputePassChain, ComputePassSchedule, ComputePassesReady, FullscreenComputePass, TrailFronts,
+};
+use food_map::FoodMap;
+use headless::HeadlessSettings;
+use midi::MidiLearn;
+use palette::{Palette, PaletteLut};
+use param_target::{ParamOffsets, ParamTarget};
+use preset::{ActivePreset, Preset};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use resize::TrailResize;
+use serde::{Deserialize, Serialize};
+use spawn_mask::{SpawnMask, SpawnMaskData};
+use std::{borrow::Cow, num::NonZeroU32, ops::RangeInclusive};
+use timeline::Timeline;
 
-const ZOOM: f32 = 1.0;
 // pub const SIZE: (u32, u32) = (3440, 1440);
 pub const WORKGROUP_SIZE: u32 = 16;
 pub const GAME_WORKGROUP_SIZE: u32 = 512;
-pub const NUM_AGENTS: u32 = 250000;
+pub const DEFAULT_NUM_AGENTS: u32 = 250000;
+pub const MAX_SPECIES: usize = 4;
+pub const FIXED_DELTA: f32 = 1.0 / 60.0;
+/// Fixed timesteps the UI and the command line accept, in seconds
+pub const FIXED_DELTA_RANGE: RangeInclusive<f32> = RangeInclusive::new(1.0 / 480.0, 0.1);
+/// Every substep has its own params and copies of the step's render graph nodes
+pub const MAX_SUBSTEPS: u32 = 8;
+/// Substep params sit at dynamic offsets, which have to be aligned to 256 bytes
+const PARAMS_STRIDE: u32 = 256;
 fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::BLACK))
-        .add_plugins(
-            DefaultPlugins
-                .set(AssetPlugin {
-                    watch_for_changes: true,
-                    ..Default::default()
-                })
-                .set(WindowPlugin {
-                    // uncomment for unthrottled FPS
-                    // present_mode: bevy::window::PresentMode::Fifo,
-                    window: WindowDescriptor {
-                        mode: WindowMode::BorderlessFullscreen,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if cli::has_arg(&args, "--help") {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let options = match LaunchOptions::from_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let agent_count = AgentCount(options.agents);
+    let headless = HeadlessSettings::from_options(&options);
+
+    if let Some(headless) = &headless {
+        if let Err(err) = std::fs::create_dir_all(&headless.output) {
+            panic!("Failed to create {}: {}", headless.output.display(), err);
+        }
+        if headless.cpu || !headless::adapter_available() {
+            if let Err(err) = headless::run_cpu(headless, &options) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
+    let mut app = App::new();
+    app.insert_resource(agent_count)
+        .insert_resource(options.clone())
+        .insert_resource(ClearColor(Color::BLACK));
+
+    match headless {
+        Some(headless) => {
+            app.insert_resource(headless)
+                .add_plugins(
+                    DefaultPlugins
+                        .set(AssetPlugin {
+                            watch_for_changes: true,
+                            ..Default::default()
+                        })
+                        .set(WindowPlugin {
+                            add_primary_window: false,
+                            exit_on_all_closed: false,
+                            ..Default::default()
+                        })
+                        .disable::<WinitPlugin>(),
+                )
+                .add_plugin(bevy::app::ScheduleRunnerPlugin)
+                .add_system(headless::save_frames);
+        }
+        None => {
+            app.add_plugins(
+                DefaultPlugins
+                    .set(AssetPlugin {
+                        watch_for_changes: true,
                         ..Default::default()
-                    },
-                    ..Default::default()
-                }),
-        )
-        // .add_plugin(Midi)
-        .add_plugin(GameOfLifeComputePlugin)
+                    })
+                    .set(WindowPlugin {
+                        window: options.window(),
+                        ..Default::default()
+                    }),
+            )
+            .add_plugin(EguiPlugin)
+            .add_system(palette::ui_palette)
+            .add_system(timeline::ui_timeline)
+            .add_plugin(brush::BrushPlugin)
+            .add_plugin(record::RecordPlugin)
+            .add_plugin(screenshot::ScreenshotPlugin)
+            .add_plugin(midi::MidiPlugin)
+            .add_plugin(osc::OscPlugin)
+            .add_plugin(audio::AudioPlugin)
+            .add_system(ui_params);
+        }
+    }
+
+    app.add_plugin(GameOfLifeComputePlugin)
+        .add_plugin(readback::FrameReadbackPlugin)
+        .add_plugin(resize::ResizePlugin)
+        .add_plugin(preset::PresetPlugin)
+        .add_plugin(spawn_mask::SpawnMaskPlugin)
+        .add_plugin(food_map::FoodMapPlugin)
+        .add_plugin(palette::PalettePlugin)
+        .add_plugin(timeline::TimelinePlugin)
         .add_startup_system(setup)
         .add_system(update_params)
-        .add_plugin(EguiPlugin)
-        .add_system(ui_params)
         .run();
 }
 
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, windows: Res<Windows>) {
-    let window = windows.primary();
+fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut capture: ResMut<readback::FrameCapture>,
+    palette: Res<Palette>,
+    windows: Res<Windows>,
+    options: Res<LaunchOptions>,
+    headless: Option<Res<HeadlessSettings>>,
+) {
+    // Headless renders use the exact requested size, the kernels skip the padding invocations
+    let (width, height) = match &headless {
+        Some(headless) => (headless.width, headless.height),
+        None => {
+            let window = windows.primary();
+            window_texture_size(window.width(), window.height())
+        }
+    };
 
-    let width = window.width().ceil() as u32
-        + (WORKGROUP_SIZE - (window.width().ceil() as u32 % WORKGROUP_SIZE));
-    let height = window.height().ceil() as u32
-        + (WORKGROUP_SIZE - (window.height().ceil() as u32 % WORKGROUP_SIZE));
+    let trail_images = TrailImages {
+        images: [
+            images.add(trail_image(width, height)),
+            images.add(trail_image(width, height)),
+        ],
+        latest: 0,
+        passes_running: false,
+    };
 
-    let mut image = Image::new_fill(
+    let mut display_image = Image::new_fill(
         Extent3d {
             width,
             height,
@@ -76,9 +198,12 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, windows: Res
         &[0, 0, 0, 255],
         TextureFormat::Rgba8Unorm,
     );
-    image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let image = images.add(image);
+    display_image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+    let display_image = DisplayImage(images.add(display_image));
+    let palette_lut = PaletteLut(images.add(palette.lut_image()));
 
     let mut image_second = Image::new_fill(
         Extent3d {
@@ -94,27 +219,33 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, windows: Res
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
     let image_second = images.add(image_second);
 
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(width as f32, height as f32)),
-            ..default()
-        },
-        texture: image.clone(),
-        ..default()
-    });
-    commands.spawn(Camera2dBundle {
-        transform: Transform {
-            scale: Vec3 {
-                x: ZOOM,
-                y: ZOOM,
-                z: ZOOM,
+    if headless.is_some() {
+        capture.enabled = true;
+        capture.wait = true;
+    } else {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(width as f32, height as f32)),
+                    ..default()
+                },
+                texture: display_image.0.clone(),
+                ..default()
+            },
+            TrailSprite,
+        ));
+        commands.spawn(Camera2dBundle {
+            transform: Transform {
+                scale: Vec3::splat(options.zoom),
+                ..default()
             },
             ..default()
-        },
-        ..default()
-    });
+        });
+    }
 
-    commands.insert_resource(GameOfLifeImage(image));
+    commands.insert_resource(trail_images);
+    commands.insert_resource(display_image);
+    commands.insert_resource(palette_lut);
 
     commands.insert_resource(GameOfLifeImageSecond(image_second));
 
@@ -160,30 +291,30 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, windows: Res
                     end: 400.0,
                 },
             ),
+            RandInfo::new(
+                RandomizableParams::BlurRadius,
+                RandParams {
+                    modifier: None,
+                    start: 1.0,
+                    end: 8.0,
+                },
+            ),
+            RandInfo::new(
+                RandomizableParams::BlurSigma,
+                RandParams {
+                    modifier: None,
+                    start: 0.5,
+                    end: 4.0,
+                },
+            ),
         ],
     };
 
     commands.insert_resource(randomizable_array);
 
-    let sim_params = SimParams {
-        width,
-        height,
-        mode: SimSpawnMode::CircleIn,
-        trail_weight: 0.75,
-        decay_rate: 0.3,
-        time: 0.01,
-        delta: 0.01,
-        salt: rand::random::<u32>(),
-        move_speed: 100.0,
-        turn_speed: 20.0,
-        sensor_angle_spacing: 30.0,
-        sensor_offset_distance: 60.0,
-        sensor_size: 1,
-        blur_mask: Color32::from_rgb(255, 240, 0),
-        color: Color32::from_rgb(255, 255, 255),
-    };
+    commands.insert_resource(default_sim_params(width, height));
 
-    commands.insert_resource(sim_params);
+    let seed = options.seed.unwrap_or_else(rand::random);
 
     let sim_settings = SimSettings {
         width,
@@ -191,11 +322,68 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, windows: Res
         randomize: false,
         state: SimState::Initialize,
         params_change_per_frame: 0.01,
+        seed,
+        fixed_timestep: true,
+        fixed_delta: options.fixed_delta,
+        // Headless frames are spaced evenly at the requested framerate
+        locked_frame_time: headless.as_ref().map(|headless| headless.frame_delta()),
+        accumulator: 0.0,
+        substeps: options.substeps,
+        time_scale: 1.0,
+        frame_steps: 1,
+        step_delta: 0.0,
     };
 
     commands.insert_resource(sim_settings);
+    commands.insert_resource(SimRng(StdRng::seed_from_u64(seed as u64)));
+
+    commands.insert_resource(EguiState {
+        all_visible: true,
+        selected_species: 0,
+        preset_path: options.preset.clone(),
+        preset_status: String::new(),
+        mask_path: String::new(),
+        food_path: String::new(),
+        dragged_agents: None,
+    })
+}
+
+fn default_sim_params(width: u32, height: u32) -> SimParams {
+    let species = SpeciesParams::default();
 
-    commands.insert_resource(EguiState { all_visible: true })
+    SimParams {
+        width,
+        height,
+        trail_weight: 0.75,
+        decay_rate: 0.3,
+        blur_radius: 1.0,
+        blur_sigma: 1.0,
+        boundary_mode: BoundaryMode::RandomTurn,
+        food_weight: 1.0,
+        // Species start out in their own colors, the palette shows all of them through one gradient
+        palette_enabled: false,
+        brush: BrushStroke::default(),
+        time: 0.0,
+        delta: 0.01,
+        salt: 0,
+        blur_mask: Color32::from_rgb(255, 240, 0),
+        species_count: 1,
+        species: [
+            species,
+            SpeciesParams {
+                color: Color32::from_rgb(255, 60, 60),
+                ..species
+            },
+            SpeciesParams {
+                color: Color32::from_rgb(60, 255, 60),
+                ..species
+            },
+            SpeciesParams {
+                color: Color32::from_rgb(60, 60, 255),
+                ..species
+            },
+        ],
+    }
 }
 
 #[derive(Debug, Clone, Copy, Resource)]
@@ -205,9 +393,92 @@ struct SimSettings {
     randomize: bool,
     state: SimState,
     params_change_per_frame: f32,
+    /// Restarting with the same seed spawns the same agents and randomizes the same params
+    seed: u32,
+    /// Always step by `fixed_delta`, running as many steps each frame as the elapsed time adds
+    /// up to, so the agents move the same at any framerate and frame hitches do not make them jump
+    fixed_timestep: bool,
+    fixed_delta: f32,
+    /// Seconds every frame stands for while recording or rendering headless, in place of the
+    /// measured frame time, so each frame advances exactly the same amount
+    locked_frame_time: Option<f32>,
+    /// Scaled time the fixed timestep has not simulated yet
+    accumulator: f32,
+    /// Simulation steps per frame without the fixed timestep, each one advancing an equal part
+    /// of the frame, and per fixed step with it
+    substeps: u32,
+    /// Slows down or speeds up the simulation, faster than about 2 wants more steps per frame
+    time_scale: f32,
+    /// Simulation steps in the current frame and the seconds each one advances, set by
+    /// `advance_steps`
+    frame_steps: u32,
+    step_delta: f32,
 }
 
-#[derive(Debug, Clone, Copy, Resource)]
+impl SimSettings {
+    /// Seconds a frame that took `frame_time` stands for, before the time scale
+    fn frame_delta(&self, frame_time: f32) -> f32 {
+        self.locked_frame_time.unwrap_or(frame_time)
+    }
+
+    /// Seconds the simulation advances for a frame that took `frame_time`, before the fixed
+    /// timestep rounds it to whole steps
+    fn delta(&self, frame_time: f32) -> f32 {
+        self.frame_delta(frame_time) * self.time_scale
+    }
+
+    /// Works out `frame_steps` and `step_delta` for a frame that took `frame_time`
+    fn advance(&mut self, frame_time: f32) {
+        let delta = self.delta(frame_time);
+        (self.frame_steps, self.step_delta) = match self.state {
+            SimState::Playing if self.fixed_timestep && self.locked_frame_time.is_none() => {
+                let substeps = self.substeps.clamp(1, MAX_SUBSTEPS);
+                self.accumulator += delta;
+                let steps =
+                    ((self.accumulator / self.fixed_delta) as u32).min(MAX_SUBSTEPS / substeps);
+                // Time past the last step that fits is dropped, rather than catching up later
+                self.accumulator =
+                    (self.accumulator - steps as f32 * self.fixed_delta) % self.fixed_delta;
+                (steps * substeps, self.fixed_delta / substeps as f32)
+            }
+            SimState::Playing => {
+                let steps = self.substeps.clamp(1, MAX_SUBSTEPS);
+                (steps, delta / steps as f32)
+            }
+            // Spawning and the brush still run once, paused frames keep the time not yet stepped
+            SimState::Initialize => {
+                self.accumulator = 0.0;
+                (1, delta)
+            }
+            SimState::Paused => (1, delta),
+        };
+    }
+
+    /// Simulation steps this frame, may be none while the fixed timestep waits for more time
+    fn steps(&self) -> u32 {
+        self.frame_steps
+    }
+
+    /// Seconds the params change by for a frame that took `frame_time`. With the fixed timestep
+    /// that is the whole steps the last frame ran, the same time the agents moved.
+    fn params_delta(&self, frame_time: f32) -> f32 {
+        match self.state {
+            SimState::Playing if self.fixed_timestep && self.locked_frame_time.is_none() => {
+                self.frame_steps as f32 * self.step_delta
+            }
+            _ => self.delta(frame_time),
+        }
+    }
+}
+
+/// Drives the params randomizer, reseeded from `SimSettings::seed` on every restart
+#[derive(Resource, Deref, DerefMut)]
+struct SimRng(StdRng);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource, ExtractResource)]
+struct AgentCount(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource)]
 enum SimState {
     Initialize,
     Playing,
@@ -229,8 +500,8 @@ struct RandParams {
 }
 
 impl RandParams {
-    fn random(&self) -> f32 {
-        let mut random = rand::random::<f32>();
+    fn random(&self, rng: &mut impl Rng) -> f32 {
+        let mut random = rng.gen::<f32>();
 
         match self.modifier {
             Some(m) => random = m(random),
@@ -242,12 +513,15 @@ impl RandParams {
 }
 
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum RandomizableParams {
     DecayRate,
     MoveSpeed,
     TurnSpeed,
     SensorAngleSpacing,
     SensorOffsetDistance,
+    BlurRadius,
+    BlurSigma,
 }
 
 #[derive(Resource)]
@@ -281,48 +555,118 @@ struct RandArray {
 #[derive(Resource)]
 struct EguiState {
     all_visible: bool,
+    selected_species: usize,
+    preset_path: String,
+    preset_status: String,
+    mask_path: String,
+    food_path: String,
+    /// Agent count the slider is being dragged to, applied once it is released
+    dragged_agents: Option<u32>,
+}
+
+/// Jumps every randomizable param to a new random value, species params to one each
+fn randomize_params(sim_params: &mut SimParams, rand_array: &RandArray, rng: &mut StdRng) {
+    for param in rand_array.array.iter() {
+        match param.index {
+            RandomizableParams::DecayRate => {
+                sim_params.decay_rate = param.params.random(rng);
+                continue;
+            }
+            RandomizableParams::BlurRadius => {
+                sim_params.blur_radius = param.params.random(rng);
+                continue;
+            }
+            RandomizableParams::BlurSigma => {
+                sim_params.blur_sigma = param.params.random(rng);
+                continue;
+            }
+            _ => {}
+        }
+        for species in sim_params.active_species_mut() {
+            match param.index {
+                RandomizableParams::DecayRate
+                | RandomizableParams::BlurRadius
+                | RandomizableParams::BlurSigma => {}
+                RandomizableParams::MoveSpeed => species.move_speed = param.params.random(rng),
+                RandomizableParams::TurnSpeed => species.turn_speed = param.params.random(rng),
+                RandomizableParams::SensorAngleSpacing => {
+                    species.sensor_angle_spacing = param.params.random(rng)
+                }
+                RandomizableParams::SensorOffsetDistance => {
+                    species.sensor_offset_distance = param.params.random(rng)
+                }
+            }
+        }
+    }
 }
 
 fn update_params(
     mut sim_params: ResMut<SimParams>,
     mut rand_array: ResMut<RandArray>,
+    mut rng: ResMut<SimRng>,
     time: Res<Time>,
     settings: Res<SimSettings>,
 ) {
+    if let SimState::Initialize = settings.state {
+        rng.0 = StdRng::seed_from_u64(settings.seed as u64);
+        for param in rand_array.array.iter_mut() {
+            param.changing = false;
+            param.value = 0.0;
+        }
+    }
+
     if !settings.randomize || settings.params_change_per_frame == 0.0 {
         return;
     }
-    let change = time.delta_seconds() * settings.params_change_per_frame;
+    let change = settings.params_delta(time.delta_seconds()) * settings.params_change_per_frame;
 
     for mut param in rand_array.array.iter_mut() {
         if !param.changing {
-            let new_value = param.params.random();
+            let new_value = param.params.random(&mut rng.0);
             param.step = new_value - param.value;
             param.target = new_value < param.value;
             param.value = new_value;
             param.changing = true;
         }
 
+        // Species keep their offsets to each other, the first one decides when the target is reached
+        let step = param.step * change;
         let current = match param.index {
             RandomizableParams::DecayRate => {
-                sim_params.decay_rate += param.step * change;
+                sim_params.decay_rate += step;
                 sim_params.decay_rate
             }
+            RandomizableParams::BlurRadius => {
+                sim_params.blur_radius += step;
+                sim_params.blur_radius
+            }
+            RandomizableParams::BlurSigma => {
+                sim_params.blur_sigma += step;
+                sim_params.blur_sigma
+            }
             RandomizableParams::MoveSpeed => {
-                sim_params.move_speed += param.step * change;
-                sim_params.move_speed
+                for species in sim_params.active_species_mut() {
+                    species.move_speed += step;
+                }
+                sim_params.species[0].move_speed
             }
             RandomizableParams::TurnSpeed => {
-                sim_params.turn_speed += param.step * change;
-                sim_params.turn_speed
+                for species in sim_params.active_species_mut() {
+                    species.turn_speed += step;
+                }
+                sim_params.species[0].turn_speed
             }
             RandomizableParams::SensorAngleSpacing => {
-                sim_params.sensor_angle_spacing += param.step * change;
-                sim_params.sensor_angle_spacing
+                for species in sim_params.active_species_mut() {
+                    species.sensor_angle_spacing += step;
+                }
+                sim_params.species[0].sensor_angle_spacing
             }
             RandomizableParams::SensorOffsetDistance => {
-                sim_params.sensor_offset_distance += param.step * change;
-                sim_params.sensor_offset_distance
+                for species in sim_params.active_species_mut() {
+                    species.sensor_offset_distance += step;
+                }
+                sim_params.species[0].sensor_offset_distance
             }
         };
 
@@ -335,13 +679,25 @@ fn update_params(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn ui_params(
     mut egui_context: ResMut<EguiContext>,
     mut sim_params: ResMut<SimParams>,
     mut sim_settings: ResMut<SimSettings>,
     mut egui_state: ResMut<EguiState>,
+    mut agent_count: ResMut<AgentCount>,
+    mut active_preset: ResMut<ActivePreset>,
+    mut rng: ResMut<SimRng>,
+    mut spawn_mask: ResMut<SpawnMask>,
+    mut food_map: ResMut<FoodMap>,
+    mut brush: ResMut<Brush>,
     keys: Res<Input<KeyCode>>,
     rand_array: Res<RandArray>,
+    // Bevy systems take at most 16 params
+    (palette, timeline): (Res<Palette>, Res<Timeline>),
+    mut trail_resize: ResMut<TrailResize>,
+    mut midi_learn: ResMut<MidiLearn>,
+    asset_server: Res<AssetServer>,
 ) {
     if keys.just_pressed(KeyCode::Escape) {
         egui_state.all_visible = !egui_state.all_visible
@@ -363,50 +719,66 @@ fn ui_params(
                 _ => sim_settings.state = SimState::Playing,
             }
         }
-        ComboBox::from_label("Spawn Mode")
-            .selected_text(format!("{:?}", sim_params.mode))
-            .show_ui(ui, |ui| {
-                if ui
-                    .selectable_value(&mut sim_params.mode, SimSpawnMode::CenterOut, "Center Out")
-                    .clicked()
-                {
-                    sim_settings.state = SimState::Initialize;
-                };
-                if ui
-                    .selectable_value(&mut sim_params.mode, SimSpawnMode::CircleIn, "Circle In")
-                    .clicked()
-                {
-                    sim_settings.state = SimState::Initialize;
-                };
-                if ui
-                    .selectable_value(
-                        &mut sim_params.mode,
-                        SimSpawnMode::FullscreenRandom,
-                        "Fullscreen Random",
-                    )
-                    .clicked()
-                {
+        ui.horizontal(|ui| {
+            if ui
+                .add(DragValue::new(&mut sim_settings.seed).prefix("seed: "))
+                .changed()
+            {
+                sim_settings.state = SimState::Initialize;
+            }
+            if ui.add(Button::new("New Seed")).clicked() {
+                sim_settings.seed = rand::random();
+                sim_settings.state = SimState::Initialize;
+            }
+            if ui.add(Button::new("Restart")).clicked() {
+                sim_settings.state = SimState::Initialize;
+            }
+        });
+        ui.add(Checkbox::new(
+            &mut sim_settings.fixed_timestep,
+            "Fixed Timestep",
+        ));
+        ui.add(Checkbox::new(
+            &mut trail_resize.rescale,
+            "Rescale Trail on Resize",
+        ));
+        // Recording steps by the video frame time in place of the fixed timestep
+        ui.add_enabled(
+            sim_settings.fixed_timestep && sim_settings.locked_frame_time.is_none(),
+            Slider::new(&mut sim_settings.fixed_delta, FIXED_DELTA_RANGE)
+                .text("fixed_delta")
+                .logarithmic(true),
+        );
+        ui.add(Slider::new(&mut sim_settings.substeps, 1..=MAX_SUBSTEPS).text("substeps"));
+        ui.add(
+            Slider::new(&mut sim_settings.time_scale, 0.05..=8.0)
+                .text("time_scale")
+                .logarithmic(true),
+        );
+        // Every new count reallocates and respawns the agents, so drags only apply on release
+        let mut num_agents = egui_state.dragged_agents.unwrap_or(agent_count.0);
+        let response = ui.add(
+            Slider::new(&mut num_agents, RangeInclusive::<u32>::new(1, 2_000_000))
+                .text("agents")
+                .logarithmic(true),
+        );
+        if response.changed() {
+            egui_state.dragged_agents = Some(num_agents);
+        }
+        if response.drag_released() || (response.changed() && !response.dragged()) {
+            if let Some(num_agents) = egui_state.dragged_agents.take() {
+                if agent_count.0 != num_agents {
+                    agent_count.0 = num_agents;
                     sim_settings.state = SimState::Initialize;
-                };
-            });
+                }
+            }
+        }
         ui.add(Checkbox::new(
             &mut sim_settings.randomize,
             "Randomize Params",
         ));
         if ui.add(Button::new("Randomize Params")).clicked() {
-            for param in rand_array.array.iter() {
-                match param.index {
-                    RandomizableParams::DecayRate => sim_params.decay_rate = param.params.random(),
-                    RandomizableParams::MoveSpeed => sim_params.move_speed = param.params.random(),
-                    RandomizableParams::TurnSpeed => sim_params.turn_speed = param.params.random(),
-                    RandomizableParams::SensorAngleSpacing => {
-                        sim_params.sensor_angle_spacing = param.params.random()
-                    }
-                    RandomizableParams::SensorOffsetDistance => {
-                        sim_params.sensor_offset_distance = param.params.random()
-                    }
-                }
-            }
+            randomize_params(&mut sim_params, &rand_array, &mut rng.0);
         }
         ui.add(
             Slider::new(
@@ -416,122 +788,572 @@ fn ui_params(
             .text("params_change_per_frame")
             .step_by(0.001),
         );
+
+        ui.separator();
+        ui.label(format!("Preset: {}", active_preset.path));
+        ui.text_edit_singleline(&mut egui_state.preset_path);
+        ui.horizontal(|ui| {
+            if ui.add(Button::new("Save")).clicked() {
+                let preset = Preset::capture(
+                    &sim_params,
+                    &sim_settings,
+                    &rand_array,
+                    &spawn_mask,
+                    &food_map,
+                    &palette,
+                    &timeline,
+                );
+                egui_state.preset_status = match preset.save(&active_preset.path) {
+                    Ok(()) => format!("Saved {}", active_preset.path),
+                    Err(err) => err,
+                };
+            }
+            if ui.add(Button::new("Load")).clicked() {
+                let path = egui_state.preset_path.clone();
+                active_preset.load(&asset_server, &path);
+                egui_state.preset_status = format!("Loaded {}", path);
+            }
+            if ui.add(Button::new("Save As")).clicked() {
+                let path = egui_state.preset_path.clone();
+                let preset = Preset::capture(
+                    &sim_params,
+                    &sim_settings,
+                    &rand_array,
+                    &spawn_mask,
+                    &food_map,
+                    &palette,
+                    &timeline,
+                );
+                egui_state.preset_status = match preset.save(&path) {
+                    Ok(()) => {
+                        active_preset.load(&asset_server, &path);
+                        format!("Saved {}", path)
+                    }
+                    Err(err) => err,
+                };
+            }
+        });
+        if !egui_state.preset_status.is_empty() {
+            ui.label(&egui_state.preset_status);
+        }
     });
 
     egui::Window::new("Params").show(egui_context.ctx_mut(), |ui| {
-        color_edit_button_srgba(ui, &mut sim_params.color, egui::color_picker::Alpha::Opaque);
-
         color_edit_button_srgba(
             ui,
             &mut sim_params.blur_mask,
             egui::color_picker::Alpha::Opaque,
         );
 
-        ui.add(
-            Slider::new(
-                &mut sim_params.decay_rate,
-                RangeInclusive::<f32>::new(0.01, 5.0),
-            )
-            .text("decay_rate"),
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut sim_params.decay_rate,
+                    RangeInclusive::<f32>::new(0.01, 5.0),
+                )
+                .text("decay_rate"),
+            ),
+            ParamTarget::DecayRate,
         );
-        ui.add(
-            Slider::new(
-                &mut sim_params.move_speed,
-                RangeInclusive::<f32>::new(10.0, 1000.0),
-            )
-            .text("move_speed"),
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut sim_params.trail_weight,
+                    RangeInclusive::<f32>::new(0.1, 1.2),
+                )
+                .text("trail_weight"),
+            ),
+            ParamTarget::TrailWeight,
         );
-        ui.add(
-            Slider::new(
-                &mut sim_params.turn_speed,
-                RangeInclusive::<f32>::new(0.1, 100.0),
-            )
-            .text("turn_speed"),
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut sim_params.blur_radius,
+                    RangeInclusive::<f32>::new(0.0, 16.0),
+                )
+                .text("blur_radius")
+                .step_by(1.0),
+            ),
+            ParamTarget::BlurRadius,
         );
-        ui.add(
-            Slider::new(
-                &mut sim_params.trail_weight,
-                RangeInclusive::<f32>::new(0.1, 1.2),
-            )
-            .text("trail_weight"),
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut sim_params.blur_sigma,
+                    RangeInclusive::<f32>::new(0.1, 10.0),
+                )
+                .text("blur_sigma"),
+            ),
+            ParamTarget::BlurSigma,
         );
+        ComboBox::from_label("Boundary")
+            .selected_text(format!("{:?}", sim_params.boundary_mode))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(
+                    &mut sim_params.boundary_mode,
+                    BoundaryMode::RandomTurn,
+                    "Random Turn",
+                );
+                ui.selectable_value(&mut sim_params.boundary_mode, BoundaryMode::Wrap, "Wrap");
+                ui.selectable_value(
+                    &mut sim_params.boundary_mode,
+                    BoundaryMode::Reflect,
+                    "Reflect",
+                );
+                ui.selectable_value(
+                    &mut sim_params.boundary_mode,
+                    BoundaryMode::Clamp,
+                    "Clamp and Slide",
+                );
+            });
+
+        ui.separator();
+        ui.label(format!("Food: {}", food_map.path));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut egui_state.food_path);
+            if ui.add(Button::new("Load")).clicked() {
+                let path = egui_state.food_path.clone();
+                food_map.load(&asset_server, &path);
+            }
+            if ui.add(Button::new("Clear")).clicked() {
+                food_map.clear();
+            }
+        });
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut sim_params.food_weight,
+                    RangeInclusive::<f32>::new(0.0, 10.0),
+                )
+                .text("food_weight"),
+            ),
+            ParamTarget::FoodWeight,
+        );
+
+        ui.separator();
+        ComboBox::from_label("Brush")
+            .selected_text(format!("{:?}", brush.tool))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut brush.tool, BrushTool::Deposit, "Deposit");
+                ui.selectable_value(&mut brush.tool, BrushTool::Erase, "Erase");
+                ui.selectable_value(&mut brush.tool, BrushTool::Repel, "Repel");
+            });
+        ui.label("Right-drag erases, shift-drag repels");
         ui.add(
-            Slider::new(
-                &mut sim_params.sensor_size,
-                RangeInclusive::<u32>::new(1, 10),
-            )
-            .text("sensor_size"),
+            Slider::new(&mut brush.radius, RangeInclusive::<f32>::new(1.0, 200.0))
+                .text("brush_radius"),
         );
         ui.add(
-            Slider::new(
-                &mut sim_params.sensor_angle_spacing,
-                RangeInclusive::<f32>::new(1.0, 360.0),
-            )
-            .text("sensor_angle_spacing"),
+            Slider::new(&mut brush.strength, RangeInclusive::<f32>::new(0.1, 20.0))
+                .text("brush_strength"),
+        );
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            for index in 0..sim_params.species_count as usize {
+                ui.selectable_value(
+                    &mut egui_state.selected_species,
+                    index,
+                    format!("Species {}", index + 1),
+                );
+            }
+            if (sim_params.species_count as usize) < MAX_SPECIES
+                && ui.add(Button::new("+")).clicked()
+            {
+                sim_params.species_count += 1;
+                egui_state.selected_species = sim_params.species_count as usize - 1;
+                sim_settings.state = SimState::Initialize;
+            }
+            if sim_params.species_count > 1 && ui.add(Button::new("-")).clicked() {
+                sim_params.species_count -= 1;
+                egui_state.selected_species = egui_state
+                    .selected_species
+                    .min(sim_params.species_count as usize - 1);
+                sim_settings.state = SimState::Initialize;
+            }
+        });
+
+        let selected = egui_state.selected_species;
+        let species = &mut sim_params.species[selected];
+
+        color_edit_button_srgba(ui, &mut species.color, egui::color_picker::Alpha::Opaque);
+
+        ComboBox::from_label("Spawn Mode")
+            .selected_text(format!("{:?}", species.mode))
+            .show_ui(ui, |ui| {
+                for (mode, label) in SimSpawnMode::ALL {
+                    if ui
+                        .selectable_value(&mut species.mode, mode, label)
+                        .clicked()
+                    {
+                        sim_settings.state = SimState::Initialize;
+                    };
+                }
+            });
+        if spawn_shape_ui(ui, species) {
+            sim_settings.state = SimState::Initialize;
+        }
+        if species.mode == SimSpawnMode::ImageMask {
+            ui.label(format!("Mask: {}", spawn_mask.path));
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut egui_state.mask_path);
+                if ui.add(Button::new("Load")).clicked() {
+                    let path = egui_state.mask_path.clone();
+                    spawn_mask.load(&asset_server, &path);
+                }
+            });
+            if ui
+                .add(Checkbox::new(
+                    &mut species.follow_mask_gradient,
+                    "Follow Gradient",
+                ))
+                .changed()
+            {
+                sim_settings.state = SimState::Initialize;
+            }
+        }
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut species.move_speed,
+                    RangeInclusive::<f32>::new(10.0, 1000.0),
+                )
+                .text("move_speed"),
+            ),
+            ParamTarget::MoveSpeed(selected),
+        );
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut species.turn_speed,
+                    RangeInclusive::<f32>::new(0.1, 100.0),
+                )
+                .text("turn_speed"),
+            ),
+            ParamTarget::TurnSpeed(selected),
         );
         ui.add(
-            Slider::new(
-                &mut sim_params.sensor_offset_distance,
-                RangeInclusive::<f32>::new(1.0, 1000.0),
-            )
-            .text("sensor_offset_distance"),
+            Slider::new(&mut species.sensor_size, RangeInclusive::<u32>::new(1, 10))
+                .text("sensor_size"),
+        );
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut species.sensor_angle_spacing,
+                    RangeInclusive::<f32>::new(1.0, 360.0),
+                )
+                .text("sensor_angle_spacing"),
+            ),
+            ParamTarget::SensorAngleSpacing(selected),
+        );
+        midi_learn.watch(
+            ui.add(
+                Slider::new(
+                    &mut species.sensor_offset_distance,
+                    RangeInclusive::<f32>::new(1.0, 1000.0),
+                )
+                .text("sensor_offset_distance"),
+            ),
+            ParamTarget::SensorOffsetDistance(selected),
         );
     });
 }
 
+/// The shape parameters of the selected spawn mode, returns whether any of them changed
+fn spawn_shape_ui(ui: &mut Ui, species: &mut SpeciesParams) -> bool {
+    let (radius_label, count_label) = match species.mode {
+        SimSpawnMode::Rings => ("ring_radius", Some("rings")),
+        SimSpawnMode::Spiral => ("spiral_radius", Some("arms")),
+        SimSpawnMode::Clusters => ("cluster_spread", Some("clusters")),
+        SimSpawnMode::Line => ("line_length", None),
+        SimSpawnMode::CircleOut => ("circle_radius", None),
+        _ => return false,
+    };
+
+    let mut changed = ui
+        .add(
+            Slider::new(
+                &mut species.spawn_radius,
+                RangeInclusive::<f32>::new(0.01, 1.0),
+            )
+            .text(radius_label),
+        )
+        .changed();
+    if let Some(count_label) = count_label {
+        changed |= ui
+            .add(
+                Slider::new(&mut species.spawn_count, RangeInclusive::<u32>::new(1, 16))
+                    .text(count_label),
+            )
+            .changed();
+    }
+    if species.mode == SimSpawnMode::Line {
+        changed |= ui
+            .add(Checkbox::new(&mut species.spawn_vertical, "Vertical"))
+            .changed();
+    }
+    if species.mode != SimSpawnMode::CircleOut {
+        ComboBox::from_label("Heading")
+            .selected_text(format!("{:?}", species.spawn_heading))
+            .show_ui(ui, |ui| {
+                for heading in [
+                    SpawnHeading::Random,
+                    SpawnHeading::Outward,
+                    SpawnHeading::Inward,
+                    SpawnHeading::Tangent,
+                ] {
+                    changed |= ui
+                        .selectable_value(
+                            &mut species.spawn_heading,
+                            heading,
+                            format!("{:?}", heading),
+                        )
+                        .clicked();
+                }
+            });
+    }
+    changed
+}
+
 #[allow(dead_code)]
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct Agent {
     position: [f32; 2],
     angle: f32,
+    species: u32,
 }
 
+/// Fields without `#[serde(skip)]` are saved in presets, missing ones load with their defaults
 #[allow(dead_code)]
-#[derive(Debug, Copy, Clone, Resource)]
+#[derive(Debug, Copy, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
 struct SimParams {
-    color: Color32,
+    /// Color the display tints trails toward as they fade, the trails themselves are not touched
+    #[serde(with = "preset::color32")]
     blur_mask: Color32,
+    #[serde(skip)]
     width: u32,
+    #[serde(skip)]
     height: u32,
-    mode: SimSpawnMode,
     trail_weight: f32,
     decay_rate: f32,
+    /// Texels sampled on each side by the blur passes, rounded when exported
+    blur_radius: f32,
+    blur_sigma: f32,
+    boundary_mode: BoundaryMode,
+    /// How strongly the food map attracts, compared to a single texel of trail
+    food_weight: f32,
+    /// Show trail intensity through `Palette` instead of the color of each species
+    palette_enabled: bool,
+    #[serde(skip)]
+    brush: BrushStroke,
+    #[serde(skip)]
     time: f32,
+    #[serde(skip)]
     delta: f32,
+    #[serde(skip)]
     salt: u32,
+    species_count: u32,
+    species: [SpeciesParams; MAX_SPECIES],
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        default_sim_params(0, 0)
+    }
+}
+
+impl SimParams {
+    fn active_species_mut(&mut self) -> &mut [SpeciesParams] {
+        &mut self.species[..self.species_count as usize]
+    }
+
+    fn export(&self) -> SimParamsExport {
+        SimParamsExport {
+            blur_mask: self.blur_mask.to_array().map(|c| c as f32 / 255.0),
+            width: self.width,
+            height: self.height,
+            species_count: self.species_count,
+            trail_weight: self.trail_weight,
+            decay_rate: self.decay_rate,
+            blur_radius: self.blur_radius.round().max(0.0) as u32,
+            blur_sigma: self.blur_sigma,
+            boundary_mode: self.boundary_mode as u32,
+            food_weight: self.food_weight,
+            palette_enabled: self.palette_enabled as u32,
+            brush_x: self.brush.position.x,
+            brush_y: self.brush.position.y,
+            brush_radius: self.brush.radius,
+            brush_strength: self.brush.strength,
+            brush_tool: self.brush.tool.map_or(0, |tool| tool as u32),
+            brush_species: self.brush.species,
+            time: self.time,
+            delta: self.delta,
+            salt: self.salt,
+        }
+    }
+
+    fn export_species(&self) -> [SpeciesExport; MAX_SPECIES] {
+        self.species.map(|species| SpeciesExport {
+            color: species.color.to_array().map(|c| c as f32 / 255.0),
+            mode: species.mode as u32,
+            move_speed: species.move_speed,
+            turn_speed: species.turn_speed,
+            sensor_angle_spacing: species.sensor_angle_spacing,
+            sensor_offset_distance: species.sensor_offset_distance,
+            sensor_size: species.sensor_size,
+            follow_mask_gradient: species.follow_mask_gradient as u32,
+            spawn_radius: species.spawn_radius,
+            spawn_count: species.spawn_count,
+            spawn_heading: species.spawn_heading as u32,
+            spawn_vertical: species.spawn_vertical as u32,
+            _padding: 0,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SpeciesParams {
+    #[serde(with = "preset::color32")]
+    color: Color32,
+    mode: SimSpawnMode,
     move_speed: f32,
     turn_speed: f32,
     sensor_angle_spacing: f32,
     sensor_offset_distance: f32,
     sensor_size: u32,
+    /// `ImageMask` agents start heading towards brighter parts of the mask instead of randomly
+    follow_mask_gradient: bool,
+    /// Size of the procedural spawn shapes, relative to half the shorter side of the texture.
+    /// Half the length of a `Line`, relative to its own axis
+    spawn_radius: f32,
+    /// Rings, spiral arms or clusters
+    spawn_count: u32,
+    spawn_heading: SpawnHeading,
+    /// Whether a `Line` runs top to bottom instead of left to right
+    spawn_vertical: bool,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+impl Default for SpeciesParams {
+    fn default() -> Self {
+        Self {
+            color: Color32::from_rgb(255, 255, 255),
+            mode: SimSpawnMode::CircleIn,
+            move_speed: 100.0,
+            turn_speed: 20.0,
+            sensor_angle_spacing: 30.0,
+            sensor_offset_distance: 60.0,
+            sensor_size: 1,
+            follow_mask_gradient: false,
+            spawn_radius: 0.5,
+            spawn_count: 3,
+            spawn_heading: SpawnHeading::Random,
+            spawn_vertical: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum SimSpawnMode {
     CenterOut = 0,
     CircleIn = 1,
     FullscreenRandom = 2,
+    /// Weighted by the luminance of `SpawnMask`, fullscreen random until it has loaded
+    ImageMask = 3,
+    /// `spawn_count` evenly spaced rings out to `spawn_radius`
+    Rings = 4,
+    /// Archimedean spiral with `spawn_count` arms winding three times around the center
+    Spiral = 5,
+    /// `spawn_count` gaussian blobs at random positions, `spawn_radius` is their deviation
+    Clusters = 6,
+    Line = 7,
+    /// On a circle of `spawn_radius`, heading straight out
+    CircleOut = 8,
+}
+
+impl SimSpawnMode {
+    const ALL: [(SimSpawnMode, &'static str); 9] = [
+        (SimSpawnMode::CenterOut, "Center Out"),
+        (SimSpawnMode::CircleIn, "Circle In"),
+        (SimSpawnMode::FullscreenRandom, "Fullscreen Random"),
+        (SimSpawnMode::ImageMask, "Image Mask"),
+        (SimSpawnMode::Rings, "Rings"),
+        (SimSpawnMode::Spiral, "Spiral"),
+        (SimSpawnMode::Clusters, "Clusters"),
+        (SimSpawnMode::Line, "Line"),
+        (SimSpawnMode::CircleOut, "Circle Out"),
+    ];
+}
+
+/// Where agents of the procedural spawn shapes start heading, relative to the center of the
+/// shape or of their cluster
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum SpawnHeading {
+    Random = 0,
+    Outward = 1,
+    Inward = 2,
+    /// Perpendicular to the center, so rings and spirals start out rotating
+    Tangent = 3,
+}
+
+/// What happens at the edges of the texture, for moving agents, sensing and blurring alike
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum BoundaryMode {
+    /// Agents pick a new random heading, samples clamp to the edge
+    RandomTurn = 0,
+    /// The texture is a torus, so the result tiles seamlessly
+    Wrap = 1,
+    /// Agents bounce off the edges, samples mirror back into the texture
+    Reflect = 2,
+    /// Agents keep their heading and slide along the edge, samples clamp to it
+    Clamp = 3,
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Resource)]
 struct SimParamsExport {
-    color: [f32; 4],
     blur_mask: [f32; 4],
     width: u32,
     height: u32,
-    mode: u32,
+    species_count: u32,
     trail_weight: f32,
     decay_rate: f32,
     time: f32,
     delta: f32,
     salt: u32,
+    blur_radius: u32,
+    blur_sigma: f32,
+    boundary_mode: u32,
+    food_weight: f32,
+    palette_enabled: u32,
+    brush_x: f32,
+    brush_y: f32,
+    brush_radius: f32,
+    brush_strength: f32,
+    /// `BrushTool`, or 0 while not painting
+    brush_tool: u32,
+    brush_species: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpeciesExport {
+    color: [f32; 4],
+    mode: u32,
     move_speed: f32,
     turn_speed: f32,
     sensor_angle_spacing: f32,
     sensor_offset_distance: f32,
     sensor_size: u32,
+    follow_mask_gradient: u32,
+    spawn_radius: f32,
+    spawn_count: u32,
+    spawn_heading: u32,
+    spawn_vertical: u32,
+    // Pads the struct to the 16 byte array stride WGSL uses for it
+    _padding: u32,
 }
 
 impl ExtractResource for SimParams {
@@ -547,23 +1369,35 @@ pub struct GameOfLifeComputePlugin;
 
 #[derive(Resource)]
 struct SimMeta {
+    num_agents: u32,
     agents_buffer: Buffer,
     params_buffer: Buffer,
+    species_buffer: Buffer,
+    spawn_mask_buffer: Buffer,
 }
 
-#[derive(Resource)]
-struct ExtractedTime {
-    seconds_since_startup: f32,
-    delta_time: f32,
+fn create_agents_buffer(render_device: &RenderDevice, num_agents: u32) -> Buffer {
+    render_device.create_buffer(&BufferDescriptor {
+        label: Some("Agents Buffer"),
+        size: (std::mem::size_of::<Agent>() * num_agents as usize) as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
 }
 
-impl ExtractResource for ExtractedTime {
-    type Source = Time;
+/// Simulation time and the RNG behind `salt`, both restart from `SimSettings::seed` while
+/// initializing so the same seed spawns and moves the agents the same way.
+#[derive(Resource)]
+struct SimClock {
+    rng: StdRng,
+    time: f32,
+}
 
-    fn extract_resource(time: &Self::Source) -> Self {
+impl SimClock {
+    fn new(seed: u32) -> Self {
         Self {
-            seconds_since_startup: time.raw_elapsed_seconds(),
-            delta_time: time.delta_seconds(),
+            rng: StdRng::seed_from_u64(seed as u64),
+            time: 0.0,
         }
     }
 }
@@ -573,136 +1407,366 @@ impl Plugin for GameOfLifeComputePlugin {
         // Extract the game of life image resource from the main world into the render world
         // for operation on by the compute shader and display on the sprite.
 
+        let num_agents = app
+            .world
+            .get_resource_or_insert_with(|| AgentCount(DEFAULT_NUM_AGENTS))
+            .0;
+        app.init_resource::<ParamOffsets>();
+
         let render_device = app.world.resource::<RenderDevice>();
 
-        let agents_buffer = render_device.create_buffer(&BufferDescriptor {
-            label: Some("Agents Buffer"),
-            size: std::mem::size_of::<[Agent; NUM_AGENTS as usize]>() as u64,
-            usage: BufferUsages::STORAGE,
-            mapped_at_creation: false,
-        });
+        let agents_buffer = create_agents_buffer(render_device, num_agents);
 
         let params_buffer = render_device.create_buffer(&BufferDescriptor {
             label: Some("Params buffer"),
-            size: (std::mem::size_of::<SimParamsExport>() + 16
-                - (std::mem::size_of::<SimParamsExport>() % 16)) as u64,
+            size: (PARAMS_STRIDE * MAX_SUBSTEPS) as u64,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
-        app.add_plugin(ExtractResourcePlugin::<GameOfLifeImage>::default())
+        let species_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("Species buffer"),
+            size: std::mem::size_of::<[SpeciesExport; MAX_SPECIES]>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let spawn_mask_buffer =
+            spawn_mask::create_spawn_mask_buffer(render_device, &SpawnMaskData::default());
+
+        app.add_plugin(ExtractResourcePlugin::<TrailImages>::default())
+            .add_plugin(ExtractResourcePlugin::<DisplayImage>::default())
             .add_plugin(ExtractResourcePlugin::<GameOfLifeImageSecond>::default())
-            .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
+            .add_plugin(ExtractResourcePlugin::<PaletteLut>::default())
             .add_plugin(ExtractResourcePlugin::<SimSettings>::default())
-            .add_plugin(ExtractResourcePlugin::<SimParams>::default());
+            .add_plugin(ExtractResourcePlugin::<AgentCount>::default())
+            .add_plugin(ExtractResourcePlugin::<SimParams>::default())
+            .add_plugin(ExtractResourcePlugin::<ParamOffsets>::default())
+            .add_system_to_stage(CoreStage::PostUpdate, advance_steps)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                swap_trail_images.after(advance_steps),
+            );
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .init_resource::<GameOfLifePipeline>()
             .add_system_to_stage(RenderStage::Queue, queue_bind_group)
-            .init_resource::<blur::BlurPipeline>()
-            .init_resource::<decay::DecayPipeline>()
-            .init_resource::<color::ColorPipeline>()
-            // .add_system_to_stage(RenderStage::Queue, queue_bind_group)
             .insert_resource(SimMeta {
+                num_agents,
                 agents_buffer,
                 params_buffer,
+                species_buffer,
+                spawn_mask_buffer,
             })
-            .add_system_to_stage(RenderStage::Prepare, prepare_params);
-
-        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        render_graph.add_node("game_of_life", GameOfLifeNode::default());
-        render_graph
-            .add_node_edge(
-                "game_of_life",
-                bevy::render::main_graph::node::CAMERA_DRIVER,
+            .insert_resource(SimClock::new(0))
+            .add_system_to_stage(RenderStage::Prepare, prepare_agents_buffer)
+            .add_system_to_stage(RenderStage::Prepare, prepare_params)
+            .add_system_to_stage(RenderStage::Prepare, clear_trail);
+
+        // Each pass here swaps the trail textures. While playing, `brush` leaves its copy of the
+        // trail in the back texture for the agents to deposit into, they sense the front one.
+        // Every substep runs the whole chain up to and including `game_of_life`
+
+        ComputePassChain::new()
+            .substeps(MAX_SUBSTEPS, |chain| {
+                chain
+                    .pass(
+                        FullscreenComputePass::new("decay", "shaders/utils.wgsl", "decay")
+                            .run_in(&[SimState::Playing, SimState::Initialize]),
+                    )
+                    .pass(FullscreenComputePass::new(
+                        "blur_horizontal",
+                        "shaders/utils.wgsl",
+                        "blur_horizontal",
+                    ))
+                    .pass(FullscreenComputePass::new(
+                        "blur_vertical",
+                        "shaders/utils.wgsl",
+                        "blur_vertical",
+                    ))
+                    // Copies the trail through while nothing is painted, runs while paused to
+                    // paint a still frame
+                    .pass(
+                        FullscreenComputePass::new("brush", "shaders/utils.wgsl", "brush")
+                            .run_in(&[SimState::Playing, SimState::Paused])
+                            .swap_in(&[SimState::Paused]),
+                    )
+                    // Spawning draws into the front texture, only updates swap
+                    .step_node("game_of_life", GameOfLifeNode::new, &[SimState::Playing])
+            })
+            // Writes `DisplayImage` rather than the trail, and keeps it up to date in every state
+            .pass(
+                FullscreenComputePass::new("color", "shaders/utils.wgsl", "color")
+                    .run_in(&[SimState::Initialize, SimState::Playing, SimState::Paused])
+                    .swap(false),
             )
-            .unwrap();
-        render_graph.add_node("blur", blur::BlurNode::default());
-        render_graph.add_node_edge("blur", "game_of_life").unwrap();
-        render_graph.add_node("decay", decay::DecayNode::default());
-        render_graph.add_node_edge("decay", "blur").unwrap();
-        // render_graph.add_node("color", color::ColorNode::default());
-        // render_graph.add_node_edge("game_of_life", "color").unwrap();
+            .node(bevy::render::main_graph::node::CAMERA_DRIVER)
+            .build(app);
+    }
+}
+
+/// Half floats resolve decay steps far smaller than 8 bits per channel did, most of all in faint
+/// trails that used to band. Each channel holds the trail of one species, up to `MAX_SPECIES`.
+const TRAIL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// An empty trail texel, without a trail of any species for the agents to sense or to show
+const TRAIL_CLEAR: [u8; 8] = [0; 8];
+
+/// Texture size for a window, padded to whole workgroups
+fn window_texture_size(width: f32, height: f32) -> (u32, u32) {
+    let (width, height) = (width.ceil() as u32, height.ceil() as u32);
+    (
+        width + (WORKGROUP_SIZE - (width % WORKGROUP_SIZE)),
+        height + (WORKGROUP_SIZE - (height % WORKGROUP_SIZE)),
+    )
+}
+
+fn trail_image(width: u32, height: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &TRAIL_CLEAR,
+        TRAIL_FORMAT,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// The trail textures the fullscreen passes ping-pong between.
+#[derive(Clone, ExtractResource, Resource)]
+struct TrailImages {
+    images: [Handle<Image>; 2],
+    /// Index of the texture that holds the trail once this frame has rendered
+    latest: usize,
+    /// Whether the fullscreen passes run this frame, decided along with `latest` so they agree
+    passes_running: bool,
+}
+
+impl TrailImages {
+    fn latest(&self) -> &Handle<Image> {
+        &self.images[self.latest]
     }
 }
 
+/// What is shown on screen and read back, the trail as colored by the `color` pass.
 #[derive(Clone, Deref, ExtractResource, Resource)]
-struct GameOfLifeImage(Handle<Image>);
+struct DisplayImage(Handle<Image>);
+
+/// Marks the sprite that shows `DisplayImage`
+#[derive(Component)]
+struct TrailSprite;
+
+/// Runs after everything that changes the state this frame, so the steps match what gets extracted
+fn advance_steps(mut settings: ResMut<SimSettings>, time: Res<Time>) {
+    let previous = (settings.frame_steps, settings.step_delta);
+    settings
+        .bypass_change_detection()
+        .advance(time.delta_seconds());
+    // The accumulator is only read here, so frames that keep the same steps are not extracted again
+    if (settings.frame_steps, settings.step_delta) != previous {
+        settings.set_changed();
+    }
+}
+
+fn swap_trail_images(
+    mut trail_images: ResMut<TrailImages>,
+    schedule: Res<ComputePassSchedule>,
+    settings: Res<SimSettings>,
+    passes_ready: Res<ComputePassesReady>,
+) {
+    let running = passes_ready.get();
+    if trail_images.passes_running != running {
+        trail_images.passes_running = running;
+    }
+    // An even number of swaps leaves the same texture in front
+    if running && schedule.swaps(&settings) % 2 == 1 {
+        trail_images.latest ^= 1;
+    }
+}
 
 #[derive(Clone, Deref, ExtractResource, Resource)]
 struct GameOfLifeImageSecond(Handle<Image>);
 
+/// Bind group `i` reads and draws into trail texture `i`, and has the other one as `texture_out`
 #[derive(Resource)]
-struct GameOfLifeImageBindGroup(BindGroup);
+struct TrailBindGroups([BindGroup; 2]);
+
+fn prepare_agents_buffer(
+    mut sim_meta: ResMut<SimMeta>,
+    agent_count: Res<AgentCount>,
+    render_device: Res<RenderDevice>,
+) {
+    if sim_meta.num_agents == agent_count.0 {
+        return;
+    }
+    sim_meta.num_agents = agent_count.0;
+    sim_meta.agents_buffer = create_agents_buffer(&render_device, agent_count.0);
+}
+
+/// Size of the params uniform, padded to the 16 byte alignment of the WGSL struct
+fn params_size() -> u64 {
+    std::mem::size_of::<SimParamsExport>().div_ceil(16) as u64 * 16
+}
+
+/// Dynamic offset of the params for `substep` in the params buffer
+pub fn params_offset(substep: u32) -> u32 {
+    substep * PARAMS_STRIDE
+}
 
 fn prepare_params(
     sim_meta: Res<SimMeta>,
     render_queue: Res<RenderQueue>,
-    time: Res<ExtractedTime>,
+    settings: Res<SimSettings>,
+    mut clock: ResMut<SimClock>,
     mut sim_params: ResMut<SimParams>,
+    param_offsets: Res<ParamOffsets>,
 ) {
-    sim_params.time = time.seconds_since_startup;
-    sim_params.delta = time.delta_time;
-    sim_params.salt = rand::random::<u32>();
-
-    let export = SimParamsExport {
-        color: sim_params.color.to_array().map(|c| c as f32 / 255.0),
-        blur_mask: sim_params.blur_mask.to_array().map(|c| c as f32 / 255.0),
-        width: sim_params.width,
-        height: sim_params.height,
-        mode: sim_params.mode as u32,
-        trail_weight: sim_params.trail_weight,
-        decay_rate: sim_params.decay_rate,
-        time: sim_params.time,
-        delta: sim_params.delta,
-        salt: sim_params.salt,
-        move_speed: sim_params.move_speed,
-        turn_speed: sim_params.turn_speed,
-        sensor_angle_spacing: sim_params.sensor_angle_spacing,
-        sensor_offset_distance: sim_params.sensor_offset_distance,
-        sensor_size: sim_params.sensor_size,
-    };
+    let steps = settings.steps();
+    sim_params.delta = settings.step_delta;
+    if let SimState::Initialize = settings.state {
+        *clock = SimClock::new(settings.seed);
+    }
+    // The passes outside the steps still read the first params when no step runs
+    for step in 0..steps.max(1) {
+        // Paused frames leave the clock alone, so pausing does not change what happens next
+        match settings.state {
+            SimState::Initialize => sim_params.salt = clock.rng.gen(),
+            SimState::Playing if step < steps => {
+                clock.time += sim_params.delta;
+                sim_params.salt = clock.rng.gen();
+            }
+            SimState::Playing | SimState::Paused => {}
+        }
+        sim_params.time = clock.time;
+        render_queue.write_buffer(
+            &sim_meta.params_buffer,
+            params_offset(step) as u64,
+            bytemuck::cast_slice(&[param_offsets.apply(&sim_params).export()]),
+        );
+    }
+
+    let species_export = param_offsets.apply(&sim_params).export_species();
+
+    render_queue.write_buffer(
+        &sim_meta.species_buffer,
+        0,
+        bytemuck::cast_slice(&species_export),
+    )
+}
 
-    render_queue.write_buffer(&sim_meta.params_buffer, 0, bytemuck::cast_slice(&[export]))
+/// Clears the trail when a restart begins, so the run only depends on its seed
+fn clear_trail(
+    settings: Res<SimSettings>,
+    trail_images: Res<TrailImages>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_queue: Res<RenderQueue>,
+    mut was_initializing: Local<bool>,
+) {
+    let initializing = matches!(settings.state, SimState::Initialize);
+    let restarted = initializing && !*was_initializing;
+    *was_initializing = initializing;
+    if !restarted {
+        return;
+    }
+
+    for image in trail_images.images.iter() {
+        let gpu_image = match gpu_images.get(image) {
+            Some(gpu_image) => gpu_image,
+            None => return,
+        };
+        let width = gpu_image.size.x as u32;
+        let height = gpu_image.size.y as u32;
+        render_queue.write_texture(
+            gpu_image.texture.as_image_copy(),
+            &TRAIL_CLEAR.repeat((width * height) as usize),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(width * TRAIL_CLEAR.len() as u32),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn queue_bind_group(
     mut commands: Commands,
     pipeline: Res<GameOfLifePipeline>,
     gpu_images: Res<RenderAssets<Image>>,
-    game_of_life_image: Res<GameOfLifeImage>,
+    trail_images: Res<TrailImages>,
     game_of_life_image_second: Res<GameOfLifeImageSecond>,
+    display_image: Res<DisplayImage>,
+    palette_lut: Res<PaletteLut>,
     sim_meta: Res<SimMeta>,
     render_device: Res<RenderDevice>,
 ) {
-    let view = &gpu_images[&game_of_life_image.0];
+    let views = trail_images.images.clone().map(|image| &gpu_images[&image]);
 
     let view_second = &gpu_images[&game_of_life_image_second.0];
+    let view_display = &gpu_images[&display_image.0];
+    let view_lut = &gpu_images[&palette_lut.0];
 
-    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.texture_bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(&view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::TextureView(&view_second.texture_view),
-            },
-            BindGroupEntry {
-                binding: 2,
-                resource: sim_meta.agents_buffer.as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 3,
-                resource: sim_meta.params_buffer.as_entire_binding(),
-            },
-        ],
+    let bind_groups = [0, 1].map(|front| {
+        render_device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.texture_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&views[front].texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&view_second.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: sim_meta.agents_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &sim_meta.params_buffer,
+                        offset: 0,
+                        size: BufferSize::new(params_size()),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: sim_meta.species_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&views[1 - front].texture_view),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: sim_meta.spawn_mask_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: BindingResource::TextureView(&view_display.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: BindingResource::TextureView(&view_lut.texture_view),
+                },
+            ],
+        })
     });
-    commands.insert_resource(GameOfLifeImageBindGroup(bind_group));
+    commands.insert_resource(TrailBindGroups(bind_groups));
 }
 
 #[derive(Resource)]
@@ -725,7 +1789,7 @@ impl FromWorld for GameOfLifePipeline {
                             visibility: ShaderStages::COMPUTE,
                             ty: BindingType::StorageTexture {
                                 access: StorageTextureAccess::ReadWrite,
-                                format: TextureFormat::Rgba8Unorm,
+                                format: TRAIL_FORMAT,
                                 view_dimension: TextureViewDimension::D2,
                             },
                             count: None,
@@ -760,11 +1824,61 @@ impl FromWorld for GameOfLifePipeline {
                             visibility: ShaderStages::COMPUTE,
                             ty: BindingType::Buffer {
                                 ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(params_size()),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::ReadWrite,
+                                format: TRAIL_FORMAT,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 6,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
                                 has_dynamic_offset: false,
                                 min_binding_size: None,
                             },
                             count: None,
                         },
+                        BindGroupLayoutEntry {
+                            binding: 7,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: TextureFormat::Rgba8Unorm,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 8,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: false },
+                                view_dimension: TextureViewDimension::D1,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
                     ],
                 });
         let shader = world
@@ -794,6 +1908,17 @@ impl FromWorld for GameOfLifePipeline {
     }
 }
 
+impl GameOfLifePipeline {
+    pub fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        [self.init_pipeline, self.update_pipeline].iter().all(|id| {
+            matches!(
+                pipeline_cache.get_compute_pipeline_state(*id),
+                CachedPipelineState::Ok(_)
+            )
+        })
+    }
+}
+
 enum GameOfLifeState {
     Stopped,
     Init,
@@ -803,12 +1928,15 @@ enum GameOfLifeState {
 #[derive(Resource)]
 struct GameOfLifeNode {
     state: GameOfLifeState,
+    /// Only the first substep initializes the agents, the others just update them
+    substep: u32,
 }
 
-impl Default for GameOfLifeNode {
-    fn default() -> Self {
+impl GameOfLifeNode {
+    fn new(substep: u32) -> Self {
         Self {
             state: GameOfLifeState::Stopped,
+            substep,
         }
     }
 }
@@ -838,8 +1966,10 @@ impl render_graph::Node for GameOfLifeNode {
             GameOfLifeState::Update => {}
         }
 
-        match settings.state {
-            SimState::Initialize => self.state = GameOfLifeState::Init,
+        // Only restart once the init pipeline has loaded, otherwise `run` has nothing to dispatch
+        match (settings.state, &self.state) {
+            (SimState::Initialize, GameOfLifeState::Stopped) => {}
+            (SimState::Initialize, _) => self.state = GameOfLifeState::Init,
             _ => {}
         }
     }
@@ -850,34 +1980,48 @@ impl render_graph::Node for GameOfLifeNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let texture_bind_group = &world.resource::<GameOfLifeImageBindGroup>().0;
+        let settings = &world.resource::<SimSettings>();
+        if self.substep >= settings.steps() {
+            return Ok(());
+        }
+        let front = world
+            .resource::<TrailFronts>()
+            .get("game_of_life", self.substep);
+        let texture_bind_group = &world.resource::<TrailBindGroups>().0[front];
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<GameOfLifePipeline>();
-        let settings = &world.resource::<SimSettings>();
+        let workgroups = world
+            .resource::<SimMeta>()
+            .num_agents
+            .div_ceil(GAME_WORKGROUP_SIZE);
 
         let mut pass = render_context
             .command_encoder
             .begin_compute_pass(&ComputePassDescriptor::default());
 
-        pass.set_bind_group(0, texture_bind_group, &[]);
+        pass.set_bind_group(0, texture_bind_group, &[params_offset(self.substep)]);
 
         // select the pipeline based on the current state
         match settings.state {
             SimState::Playing | SimState::Initialize => match self.state {
                 GameOfLifeState::Stopped => {}
+                GameOfLifeState::Init if self.substep > 0 => {}
                 GameOfLifeState::Init => {
-                    let init_pipeline = pipeline_cache
-                        .get_compute_pipeline(pipeline.init_pipeline)
-                        .unwrap();
-                    pass.set_pipeline(init_pipeline);
-                    pass.dispatch_workgroups(NUM_AGENTS / GAME_WORKGROUP_SIZE, 1, 1);
+                    // Missing while its shader recompiles, the agents wait for it
+                    if let Some(init_pipeline) =
+                        pipeline_cache.get_compute_pipeline(pipeline.init_pipeline)
+                    {
+                        pass.set_pipeline(init_pipeline);
+                        pass.dispatch_workgroups(workgroups, 1, 1);
+                    }
                 }
                 GameOfLifeState::Update => {
-                    let update_pipeline = pipeline_cache
-                        .get_compute_pipeline(pipeline.update_pipeline)
-                        .unwrap();
-                    pass.set_pipeline(update_pipeline);
-                    pass.dispatch_workgroups(NUM_AGENTS / GAME_WORKGROUP_SIZE, 1, 1);
+                    if let Some(update_pipeline) =
+                        pipeline_cache.get_compute_pipeline(pipeline.update_pipeline)
+                    {
+                        pass.set_pipeline(update_pipeline);
+                        pass.dispatch_workgroups(workgroups, 1, 1);
+                    }
                 }
             },
             _ => {}
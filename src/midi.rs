@@ -0,0 +1,673 @@
+//! MIDI controller input, with MIDI-learn mappings from controls to `SimParams` fields.
+//!
+//! The port is read on midir's own thread, which forwards parsed CC and note messages over a
+//! channel to [`apply_midi`]. Anything else holding a [`MidiInput::sender`] can feed the same
+//! channel, which is how a mock controller is plugged in without a port.
+//!
+//! In learn mode, clicking a slider in the Params window picks the field it drives and the next
+//! control that moves is mapped to it. Notes map like knobs with their velocity as the value.
+use std::path::PathBuf;
+
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_egui::{
+    egui::{self, Button, Checkbox, ComboBox, DragValue, Response},
+    EguiContext,
+};
+use crossbeam_channel::{Receiver, Sender};
+use midir::MidiInputConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::{cli::LaunchOptions, param_target::ParamTarget, EguiState, SimParams};
+
+/// Where mappings are saved, relative to `assets`
+pub const DEFAULT_MIDI_MAPPINGS: &str = "midi/mappings.ron";
+
+const CLIENT_NAME: &str = "bevy_shader_test";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage {
+    ControlChange { channel: u8, control: u8, value: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+}
+
+impl MidiMessage {
+    /// Everything but CC and note messages is ignored
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (status, data) = bytes.split_first()?;
+        let channel = status & 0x0f;
+        match (status & 0xf0, data) {
+            (0x80, [note, _]) => Some(Self::NoteOff {
+                channel,
+                note: *note,
+            }),
+            // Many devices send note on with zero velocity instead of note off
+            (0x90, [note, 0]) => Some(Self::NoteOff {
+                channel,
+                note: *note,
+            }),
+            (0x90, [note, velocity]) => Some(Self::NoteOn {
+                channel,
+                note: *note,
+                velocity: *velocity,
+            }),
+            (0xb0, [control, value]) => Some(Self::ControlChange {
+                channel,
+                control: *control,
+                value: *value,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The control that sent the message and its value scaled to `0.0..=1.0`
+    fn control(&self) -> (MidiControl, f32) {
+        match *self {
+            Self::ControlChange {
+                channel,
+                control,
+                value,
+            } => (
+                MidiControl::ControlChange { channel, control },
+                value as f32 / 127.0,
+            ),
+            Self::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (MidiControl::Note { channel, note }, velocity as f32 / 127.0),
+            Self::NoteOff { channel, note } => (MidiControl::Note { channel, note }, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MidiControl {
+    ControlChange { channel: u8, control: u8 },
+    Note { channel: u8, note: u8 },
+}
+
+impl MidiControl {
+    fn label(&self) -> String {
+        match self {
+            Self::ControlChange { channel, control } => {
+                format!("CC {} ch {}", control, channel + 1)
+            }
+            Self::Note { channel, note } => format!("Note {} ch {}", note, channel + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    pub control: MidiControl,
+    pub target: ParamTarget,
+    /// Value at the control's minimum, above `max` inverts the control
+    pub min: f32,
+    pub max: f32,
+}
+
+impl MidiMapping {
+    pub fn scale(&self, value: f32) -> f32 {
+        self.min + (self.max - self.min) * value
+    }
+}
+
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+pub struct MidiMappings {
+    pub mappings: Vec<MidiMapping>,
+}
+
+impl MidiMappings {
+    /// Missing files load as no mappings
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = mappings_file(path);
+        match std::fs::read_to_string(&file) {
+            Ok(text) => ron::from_str(&text).map_err(|err| format!("{}: {}", file.display(), err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("{}: {}", file.display(), err)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = mappings_file(path);
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(&file, text).map_err(|err| format!("{}: {}", file.display(), err))
+    }
+
+    /// A control drives a single target and a target is driven by a single control
+    fn learn(&mut self, control: MidiControl, target: ParamTarget) {
+        self.mappings
+            .retain(|mapping| mapping.control != control && mapping.target != target);
+        let (min, max) = target.range();
+        self.mappings.push(MidiMapping {
+            control,
+            target,
+            min,
+            max,
+        });
+    }
+}
+
+fn mappings_file(path: &str) -> PathBuf {
+    FileAssetIo::get_base_path().join("assets").join(path)
+}
+
+#[derive(Resource)]
+pub struct MidiInput {
+    sender: Sender<MidiMessage>,
+    receiver: Receiver<MidiMessage>,
+    /// Name of the connected port
+    pub port: Option<String>,
+    /// Names of the available ports, enumerated on startup and on refresh since every listing
+    /// opens a MIDI client
+    pub ports: Vec<String>,
+    pub status: String,
+}
+
+impl Default for MidiInput {
+    fn default() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self {
+            sender,
+            receiver,
+            port: None,
+            ports: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
+
+impl MidiInput {
+    /// Feeds messages in the same way a port does
+    pub fn sender(&self) -> Sender<MidiMessage> {
+        self.sender.clone()
+    }
+}
+
+/// Keeps the port open, midir stops calling back once the connection is dropped.
+/// Not `Sync` on every backend, so it is kept as a non-send resource.
+#[derive(Default)]
+pub struct MidiConnection(Option<MidiInputConnection<()>>);
+
+/// Names of the available input ports, empty when MIDI is not available at all
+pub fn port_names() -> Vec<String> {
+    match midir::MidiInput::new(CLIENT_NAME) {
+        Ok(input) => input
+            .ports()
+            .iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Connects to the first port whose name contains `name`, or the first port without one
+fn connect(
+    name: Option<&str>,
+    sender: Sender<MidiMessage>,
+) -> Result<(String, MidiInputConnection<()>), String> {
+    let input = midir::MidiInput::new(CLIENT_NAME).map_err(|err| err.to_string())?;
+    let (port, port_name) = input
+        .ports()
+        .into_iter()
+        .filter_map(|port| {
+            let port_name = input.port_name(&port).ok()?;
+            Some((port, port_name))
+        })
+        .find(|(_, port_name)| match name {
+            Some(name) => port_name.contains(name),
+            None => true,
+        })
+        .ok_or_else(|| match name {
+            Some(name) => format!("No MIDI port matches {}", name),
+            None => "No MIDI ports".to_string(),
+        })?;
+    let connection = input
+        .connect(&port, CLIENT_NAME, forward(sender), ())
+        .map_err(|err| format!("{}: {}", port_name, err))?;
+    Ok((port_name, connection))
+}
+
+/// Opens a port other software can connect to, instead of connecting to a device
+#[cfg(unix)]
+fn connect_virtual(
+    sender: Sender<MidiMessage>,
+) -> Result<(String, MidiInputConnection<()>), String> {
+    use midir::os::unix::VirtualInput;
+
+    let input = midir::MidiInput::new(CLIENT_NAME).map_err(|err| err.to_string())?;
+    let connection = input
+        .create_virtual(CLIENT_NAME, forward(sender), ())
+        .map_err(|err| err.to_string())?;
+    Ok((format!("{} (virtual)", CLIENT_NAME), connection))
+}
+
+#[cfg(not(unix))]
+fn connect_virtual(
+    _sender: Sender<MidiMessage>,
+) -> Result<(String, MidiInputConnection<()>), String> {
+    Err("Virtual MIDI ports are not supported on this platform".to_string())
+}
+
+fn forward(sender: Sender<MidiMessage>) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+    move |_, bytes, _| {
+        if let Some(message) = MidiMessage::parse(bytes) {
+            let _ = sender.send(message);
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct MidiLearn {
+    /// Clicking a slider picks it as the target instead of only changing it
+    pub enabled: bool,
+    /// Mapped to the next control that moves
+    pub target: Option<ParamTarget>,
+    /// File mappings are saved to, relative to `assets`
+    pub path: String,
+    pub status: String,
+}
+
+impl Default for MidiLearn {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: None,
+            path: DEFAULT_MIDI_MAPPINGS.to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+impl MidiLearn {
+    /// Called with the response of every slider that can be mapped
+    pub fn watch(&mut self, response: Response, target: ParamTarget) -> Response {
+        if self.enabled && (response.clicked() || response.drag_started()) {
+            self.target = Some(target);
+        }
+        if self.target == Some(target) {
+            response.highlight()
+        } else {
+            response
+        }
+    }
+}
+
+pub struct MidiPlugin;
+
+impl Plugin for MidiPlugin {
+    fn build(&self, app: &mut App) {
+        let mut learn = MidiLearn::default();
+        let mappings = match MidiMappings::load(&learn.path) {
+            Ok(mappings) => mappings,
+            Err(err) => {
+                warn!("Failed to load MIDI mappings: {}", err);
+                learn.status = err;
+                MidiMappings::default()
+            }
+        };
+
+        let mut input = MidiInput {
+            ports: port_names(),
+            ..default()
+        };
+        let (port, midi_virtual) = match app.world.get_resource::<LaunchOptions>() {
+            Some(options) => (options.midi_port.clone(), options.midi_virtual),
+            None => (None, false),
+        };
+        let connection = if midi_virtual {
+            connect_virtual(input.sender())
+        } else {
+            connect(port.as_deref(), input.sender())
+        };
+        let connection = match connection {
+            Ok((port, connection)) => {
+                info!("Reading MIDI from {}", port);
+                input.port = Some(port);
+                Some(connection)
+            }
+            Err(err) => {
+                info!("MIDI input disabled: {}", err);
+                input.status = err;
+                None
+            }
+        };
+
+        app.insert_resource(input)
+            .insert_resource(learn)
+            .insert_resource(mappings)
+            .insert_non_send_resource(MidiConnection(connection))
+            .add_system(apply_midi)
+            .add_system(ui_midi);
+    }
+}
+
+/// Applies the messages that arrived since the last frame, or maps the first one in learn mode
+pub fn apply_midi(
+    input: Res<MidiInput>,
+    mut learn: ResMut<MidiLearn>,
+    mut mappings: ResMut<MidiMappings>,
+    mut sim_params: ResMut<SimParams>,
+) {
+    for message in input.receiver.try_iter() {
+        let (control, value) = message.control();
+        if learn.enabled {
+            if let Some(target) = learn.target.take() {
+                mappings.learn(control, target);
+                learn.status = format!("{} drives {}", control.label(), target.label());
+                continue;
+            }
+        }
+        for mapping in mappings.mappings.iter() {
+            if mapping.control != control {
+                continue;
+            }
+            let value = mapping.scale(value);
+            // Only touch the params when a value changes, they are re-uploaded on change
+            if let Some(field) = mapping.target.field(sim_params.bypass_change_detection()) {
+                if *field != value {
+                    *field = value;
+                    sim_params.set_changed();
+                }
+            }
+        }
+    }
+}
+
+fn ui_midi(
+    mut egui_context: ResMut<EguiContext>,
+    mut input: ResMut<MidiInput>,
+    mut learn: ResMut<MidiLearn>,
+    mut mappings: ResMut<MidiMappings>,
+    mut connection: NonSendMut<MidiConnection>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    egui::Window::new("MIDI").show(egui_context.ctx_mut(), |ui| {
+        let selected = input.port.clone().unwrap_or_else(|| "None".to_string());
+        let mut port = None;
+        ui.horizontal(|ui| {
+            ComboBox::from_label("Port")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in input.ports.iter() {
+                        if ui.selectable_label(*name == selected, name).clicked() {
+                            port = Some(name.clone());
+                        }
+                    }
+                });
+            if ui.add(Button::new("Refresh")).clicked() {
+                input.ports = port_names();
+            }
+        });
+        if let Some(name) = port {
+            // The old connection has to close before the port can be opened again
+            connection.0 = None;
+            match connect(Some(&name), input.sender()) {
+                Ok((name, new_connection)) => {
+                    input.port = Some(name);
+                    input.status.clear();
+                    connection.0 = Some(new_connection);
+                }
+                Err(err) => {
+                    input.port = None;
+                    input.status = err;
+                }
+            }
+        }
+        if !input.status.is_empty() {
+            ui.label(&input.status);
+        }
+
+        ui.separator();
+        if ui
+            .add(Checkbox::new(&mut learn.enabled, "MIDI Learn"))
+            .changed()
+        {
+            learn.target = None;
+        }
+        if let Some(target) = learn.target {
+            ui.label(format!("Move a control to map {}", target.label()));
+        } else if learn.enabled {
+            ui.label("Click a slider in Params, then move a control");
+        }
+
+        let mut removed = None;
+        for (index, mapping) in mappings.mappings.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} -> {}",
+                    mapping.control.label(),
+                    mapping.target.label()
+                ));
+                ui.add(DragValue::new(&mut mapping.min).speed(0.01).prefix("min: "));
+                ui.add(DragValue::new(&mut mapping.max).speed(0.01).prefix("max: "));
+                if ui.add(Button::new("x")).clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            mappings.mappings.remove(index);
+        }
+
+        ui.text_edit_singleline(&mut learn.path);
+        ui.horizontal(|ui| {
+            if ui.add(Button::new("Save")).clicked() {
+                learn.status = match mappings.save(&learn.path) {
+                    Ok(()) => format!("Saved {}", learn.path),
+                    Err(err) => err,
+                };
+            }
+            if ui.add(Button::new("Load")).clicked() {
+                learn.status = match MidiMappings::load(&learn.path) {
+                    Ok(loaded) => {
+                        *mappings = loaded;
+                        format!("Loaded {}", learn.path)
+                    }
+                    Err(err) => err,
+                };
+            }
+        });
+        if !learn.status.is_empty() {
+            ui.label(&learn.status);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROL: MidiControl = MidiControl::ControlChange {
+        channel: 0,
+        control: 7,
+    };
+
+    fn mapping(control: MidiControl, target: ParamTarget, min: f32, max: f32) -> MidiMapping {
+        MidiMapping {
+            control,
+            target,
+            min,
+            max,
+        }
+    }
+
+    #[test]
+    fn parse_messages() {
+        assert_eq!(
+            MidiMessage::parse(&[0xb3, 7, 100]),
+            Some(MidiMessage::ControlChange {
+                channel: 3,
+                control: 7,
+                value: 100
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0x90, 60, 64]),
+            Some(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 64
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0x81, 60, 64]),
+            Some(MidiMessage::NoteOff {
+                channel: 1,
+                note: 60
+            })
+        );
+        // Note on without velocity is a note off
+        assert_eq!(
+            MidiMessage::parse(&[0x9f, 60, 0]),
+            Some(MidiMessage::NoteOff {
+                channel: 15,
+                note: 60
+            })
+        );
+    }
+
+    #[test]
+    fn parse_ignores_other_messages() {
+        // Running status, data bytes without a status byte
+        assert_eq!(MidiMessage::parse(&[7, 100]), None);
+        // Pitch bend, program change, clock and sysex
+        assert_eq!(MidiMessage::parse(&[0xe0, 0, 64]), None);
+        assert_eq!(MidiMessage::parse(&[0xc0, 5]), None);
+        assert_eq!(MidiMessage::parse(&[0xf8]), None);
+        assert_eq!(MidiMessage::parse(&[0xf0, 0x7e, 0xf7]), None);
+        // Truncated and empty messages
+        assert_eq!(MidiMessage::parse(&[0xb0, 7]), None);
+        assert_eq!(MidiMessage::parse(&[]), None);
+    }
+
+    #[test]
+    fn scale_mapping() {
+        let mapping = mapping(CONTROL, ParamTarget::DecayRate, 1.0, 3.0);
+        assert_eq!(mapping.scale(0.0), 1.0);
+        assert_eq!(mapping.scale(0.5), 2.0);
+        assert_eq!(mapping.scale(1.0), 3.0);
+
+        let inverted = MidiMapping {
+            min: 3.0,
+            max: 1.0,
+            ..mapping
+        };
+        assert_eq!(inverted.scale(0.0), 3.0);
+        assert_eq!(inverted.scale(0.25), 2.5);
+        assert_eq!(inverted.scale(1.0), 1.0);
+    }
+
+    #[test]
+    fn learn_replaces_mappings() {
+        let other = MidiControl::Note {
+            channel: 0,
+            note: 60,
+        };
+        let mut mappings = MidiMappings {
+            mappings: vec![
+                mapping(CONTROL, ParamTarget::DecayRate, 0.0, 1.0),
+                mapping(other, ParamTarget::TrailWeight, 0.0, 1.0),
+                mapping(other, ParamTarget::BlurSigma, 0.0, 1.0),
+            ],
+        };
+
+        // Takes over the control's mapping and the target's mapping, and starts at the full range
+        mappings.learn(CONTROL, ParamTarget::TrailWeight);
+        let (min, max) = ParamTarget::TrailWeight.range();
+        assert_eq!(
+            mappings.mappings,
+            vec![
+                mapping(other, ParamTarget::BlurSigma, 0.0, 1.0),
+                mapping(CONTROL, ParamTarget::TrailWeight, min, max),
+            ]
+        );
+    }
+
+    fn midi_world(mappings: Vec<MidiMapping>) -> (World, SystemStage) {
+        let mut world = World::new();
+        world.insert_resource(MidiInput::default());
+        world.insert_resource(MidiLearn::default());
+        world.insert_resource(MidiMappings { mappings });
+        world.insert_resource(crate::default_sim_params(64, 64));
+        (
+            world,
+            SystemStage::single_threaded().with_system(apply_midi),
+        )
+    }
+
+    fn send(world: &World, bytes: &[u8]) {
+        let sender = world.resource::<MidiInput>().sender();
+        sender.send(MidiMessage::parse(bytes).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn apply_midi_sets_mapped_fields() {
+        let note = MidiControl::Note {
+            channel: 0,
+            note: 60,
+        };
+        let (mut world, mut stage) = midi_world(vec![
+            mapping(CONTROL, ParamTarget::DecayRate, 0.0, 2.54),
+            mapping(note, ParamTarget::MoveSpeed(1), 10.0, 137.0),
+        ]);
+
+        send(&world, &[0xb0, 7, 50]);
+        send(&world, &[0x90, 60, 127]);
+        // Unmapped controls leave the params alone
+        send(&world, &[0xb0, 8, 127]);
+        stage.run(&mut world);
+        let params = world.resource::<SimParams>();
+        assert!((params.decay_rate - 1.0).abs() < 1e-6);
+        assert_eq!(params.species[1].move_speed, 137.0);
+        assert_eq!(params.trail_weight, 0.75);
+
+        // Only the last value of a frame sticks, note off goes to the minimum
+        send(&world, &[0xb0, 7, 0]);
+        send(&world, &[0xb0, 7, 127]);
+        send(&world, &[0x90, 60, 0]);
+        stage.run(&mut world);
+        let params = world.resource::<SimParams>();
+        assert!((params.decay_rate - 2.54).abs() < 1e-6);
+        assert_eq!(params.species[1].move_speed, 10.0);
+    }
+
+    #[test]
+    fn apply_midi_learns_the_next_control() {
+        let (mut world, mut stage) = midi_world(Vec::new());
+        {
+            let mut learn = world.resource_mut::<MidiLearn>();
+            learn.enabled = true;
+            learn.target = Some(ParamTarget::BlurSigma);
+        }
+
+        // The first message only maps the control, the next one drives the field
+        send(&world, &[0xb2, 20, 0]);
+        send(&world, &[0xb2, 20, 127]);
+        stage.run(&mut world);
+        let (min, max) = ParamTarget::BlurSigma.range();
+        assert_eq!(
+            world.resource::<MidiMappings>().mappings,
+            vec![mapping(
+                MidiControl::ControlChange {
+                    channel: 2,
+                    control: 20
+                },
+                ParamTarget::BlurSigma,
+                min,
+                max
+            )]
+        );
+        assert_eq!(world.resource::<MidiLearn>().target, None);
+        assert_eq!(world.resource::<SimParams>().blur_sigma, max);
+    }
+}
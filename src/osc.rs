@@ -0,0 +1,574 @@
+//! Remote control over OSC, for audio and lighting software running next to the simulation.
+//!
+//! Messages to `/sim/<field>` set `SimParams` and `SimSettings` fields, species fields are set for
+//! every active species or for one with `/sim/species/<n>/<field>`, counting from 1. Colors take
+//! three floats from 0 to 1, or an OSC color. `/sim/reset`, `/sim/play`, `/sim/pause` and
+//! `/sim/randomize` work like the buttons, `/sim/pause` optionally takes whether to pause.
+//!
+//! Whenever a field changes, no matter where from, its new value is sent to the target under the
+//! same address, so control surfaces stay in sync.
+//!
+//! Only this machine can send messages unless `--osc-bind` listens on an address other machines
+//! reach, anyone who can reach the port can change the simulation.
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use crossbeam_channel::Receiver;
+
+use crate::{
+    cli::LaunchOptions, randomize_params, AgentCount, EguiState, RandArray, SimParams, SimRng,
+    SimSettings, SimState, MAX_SPECIES, MAX_SUBSTEPS,
+};
+
+pub const DEFAULT_OSC_PORT: u16 = 9000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    String(String),
+    Bool(bool),
+    /// RGBA
+    Color([u8; 4]),
+    /// Nil, impulse and blob arguments, which nothing here reads
+    Nil,
+}
+
+impl OscArg {
+    fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::Int(value) => Some(value as f32),
+            Self::Float(value) => Some(value),
+            Self::Long(value) => Some(value as f32),
+            Self::Double(value) => Some(value as f32),
+            Self::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            other => other.as_f32().map(|value| value != 0.0),
+        }
+    }
+
+    fn tag(&self) -> char {
+        match self {
+            Self::Int(_) => 'i',
+            Self::Float(_) => 'f',
+            Self::Long(_) => 'h',
+            Self::Double(_) => 'd',
+            Self::String(_) => 's',
+            Self::Bool(true) => 'T',
+            Self::Bool(false) => 'F',
+            Self::Color(_) => 'r',
+            Self::Nil => 'N',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArg>) -> Self {
+        Self {
+            address: address.into(),
+            args,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(OscArg::tag))
+            .collect();
+        write_string(&mut bytes, &tags);
+        for arg in self.args.iter() {
+            match arg {
+                OscArg::Int(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::Long(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::Double(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::String(value) => write_string(&mut bytes, value),
+                OscArg::Color(value) => bytes.extend(value),
+                OscArg::Bool(_) | OscArg::Nil => {}
+            }
+        }
+        bytes
+    }
+}
+
+/// Strings are null terminated and padded to 4 bytes, like every other part of a packet
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend(value.as_bytes());
+    bytes.resize((bytes.len() + 4) & !3, 0);
+}
+
+fn read_string(bytes: &[u8], offset: &mut usize) -> Option<String> {
+    let rest = bytes.get(*offset..)?;
+    let len = rest.iter().position(|&byte| byte == 0)?;
+    let value = std::str::from_utf8(&rest[..len]).ok()?.to_string();
+    *offset += (len + 4) & !3;
+    Some(value)
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], offset: &mut usize) -> Option<[u8; N]> {
+    let value = bytes.get(*offset..*offset + N)?.try_into().ok()?;
+    *offset += N;
+    Some(value)
+}
+
+/// Appends the messages of a packet, bundles are unpacked and their time tags ignored.
+/// Returns `None` for malformed packets, keeping the messages read before the error.
+pub fn decode(bytes: &[u8], messages: &mut Vec<OscMessage>) -> Option<()> {
+    if bytes.starts_with(b"#bundle\0") {
+        let mut offset = 16;
+        while offset < bytes.len() {
+            let size = u32::from_be_bytes(read_bytes(bytes, &mut offset)?) as usize;
+            decode(bytes.get(offset..offset + size)?, messages)?;
+            offset += size;
+        }
+        return Some(());
+    }
+
+    let mut offset = 0;
+    let address = read_string(bytes, &mut offset)?;
+    // Very old senders leave out the type tags of messages without arguments
+    let tags = if offset < bytes.len() {
+        read_string(bytes, &mut offset)?
+    } else {
+        ",".to_string()
+    };
+    let mut args = Vec::new();
+    for tag in tags.strip_prefix(',')?.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(read_bytes(bytes, &mut offset)?)),
+            'f' => OscArg::Float(f32::from_be_bytes(read_bytes(bytes, &mut offset)?)),
+            'h' => OscArg::Long(i64::from_be_bytes(read_bytes(bytes, &mut offset)?)),
+            'd' => OscArg::Double(f64::from_be_bytes(read_bytes(bytes, &mut offset)?)),
+            's' | 'S' => OscArg::String(read_string(bytes, &mut offset)?),
+            'r' => OscArg::Color(read_bytes(bytes, &mut offset)?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            'N' | 'I' => OscArg::Nil,
+            'b' => {
+                let size = u32::from_be_bytes(read_bytes(bytes, &mut offset)?) as usize;
+                bytes.get(offset..offset + size)?;
+                offset += (size + 3) & !3;
+                OscArg::Nil
+            }
+            _ => return None,
+        });
+    }
+    messages.push(OscMessage { address, args });
+    Some(())
+}
+
+#[derive(Resource)]
+pub struct OscRemote {
+    /// `ip:port` state changes are sent to, nothing is sent when it does not parse
+    pub target: String,
+    /// Shared by the listener thread, which reads from its own handle
+    socket: Option<UdpSocket>,
+    receiver: Option<Receiver<OscMessage>>,
+    /// Values last sent to the target, by address
+    sent: HashMap<String, Vec<OscArg>>,
+    pub status: String,
+}
+
+impl OscRemote {
+    /// Binds the port and starts the listener thread, which runs until the app exits. Bound to
+    /// loopback, only this machine can reach it and targets have to be on it too.
+    fn listen(address: IpAddr, port: u16, target: String) -> Self {
+        let mut remote = Self {
+            target,
+            socket: None,
+            receiver: None,
+            sent: HashMap::new(),
+            status: String::new(),
+        };
+        let (socket, listener) = match UdpSocket::bind((address, port))
+            .and_then(|socket| Ok((socket.try_clone()?, socket)))
+        {
+            Ok(sockets) => sockets,
+            Err(err) => {
+                remote.status = format!("Failed to listen on {}:{}: {}", address, port, err);
+                warn!("{}", remote.status);
+                return remote;
+            }
+        };
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 65536];
+            let mut messages = Vec::new();
+            loop {
+                let size = match listener.recv_from(&mut buffer) {
+                    Ok((size, _)) => size,
+                    Err(err) => {
+                        error!("OSC listener stopped: {}", err);
+                        return;
+                    }
+                };
+                if decode(&buffer[..size], &mut messages).is_none() {
+                    warn!("Ignored malformed OSC packet");
+                }
+                for message in messages.drain(..) {
+                    if sender.send(message).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        remote.status = format!("Listening on UDP port {}", port);
+        remote.socket = Some(socket);
+        remote.receiver = Some(receiver);
+        remote
+    }
+}
+
+pub struct OscPlugin;
+
+impl Plugin for OscPlugin {
+    fn build(&self, app: &mut App) {
+        let (address, port, target) = match app.world.get_resource::<LaunchOptions>() {
+            Some(options) => (
+                options.osc_bind,
+                options.osc_port,
+                options.osc_target.clone(),
+            ),
+            None => (IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_OSC_PORT, None),
+        };
+        app.insert_resource(OscRemote::listen(address, port, target.unwrap_or_default()))
+            .add_system(apply_osc)
+            .add_system(broadcast_osc.after(apply_osc))
+            .add_system(ui_osc);
+    }
+}
+
+/// Sets `field` to `value` if it differs, `None` when the arguments did not fit the field
+fn update<T: PartialEq>(field: &mut T, value: Option<T>) -> Option<bool> {
+    let value = value?;
+    if *field == value {
+        return Some(false);
+    }
+    *field = value;
+    Some(true)
+}
+
+fn color(args: &[OscArg]) -> Option<Color32> {
+    match args {
+        [OscArg::Color([r, g, b, _])] => Some(Color32::from_rgb(*r, *g, *b)),
+        [r, g, b, ..] => {
+            let [r, g, b] = [r.as_f32()?, g.as_f32()?, b.as_f32()?]
+                .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            Some(Color32::from_rgb(r, g, b))
+        }
+        _ => None,
+    }
+}
+
+fn color_args(color: Color32) -> Vec<OscArg> {
+    [color.r(), color.g(), color.b()]
+        .map(|c| OscArg::Float(c as f32 / 255.0))
+        .to_vec()
+}
+
+/// Sets the field of `SimParams` at `path`, returns whether it changed
+fn apply_param(params: &mut SimParams, path: &str, args: &[OscArg]) -> Option<bool> {
+    let value = args.first().and_then(OscArg::as_f32);
+    let (species, field) = match path.strip_prefix("species/") {
+        Some(rest) => {
+            let (index, field) = rest.split_once('/')?;
+            let index = index.parse::<usize>().ok()?.checked_sub(1)?;
+            (index..index + 1, field)
+        }
+        None => {
+            match path {
+                "decay_rate" => return update(&mut params.decay_rate, value),
+                "trail_weight" => return update(&mut params.trail_weight, value),
+                "blur_radius" => return update(&mut params.blur_radius, value),
+                "blur_sigma" => return update(&mut params.blur_sigma, value),
+                "food_weight" => return update(&mut params.food_weight, value),
+                "palette_enabled" => {
+                    return update(
+                        &mut params.palette_enabled,
+                        args.first().and_then(OscArg::as_bool),
+                    )
+                }
+                "blur_mask" => return update(&mut params.blur_mask, color(args)),
+                _ => {}
+            }
+            (0..params.species_count as usize, path)
+        }
+    };
+    if species.end > MAX_SPECIES {
+        return None;
+    }
+
+    let mut changed = false;
+    for species in params.species[species].iter_mut() {
+        changed |= match field {
+            "color" => update(&mut species.color, color(args)),
+            "move_speed" => update(&mut species.move_speed, value),
+            "turn_speed" => update(&mut species.turn_speed, value),
+            "sensor_angle_spacing" => update(&mut species.sensor_angle_spacing, value),
+            "sensor_offset_distance" => update(&mut species.sensor_offset_distance, value),
+            "sensor_size" => update(
+                &mut species.sensor_size,
+                value.map(|value| value.round().clamp(1.0, 10.0) as u32),
+            ),
+            _ => None,
+        }?;
+    }
+    Some(changed)
+}
+
+/// Sets the field of `SimSettings` at `path`, returns whether it changed
+fn apply_setting(settings: &mut SimSettings, path: &str, args: &[OscArg]) -> Option<bool> {
+    let value = args.first().and_then(OscArg::as_f32);
+    let flag = args.first().and_then(OscArg::as_bool);
+    match path {
+        "substeps" => update(
+            &mut settings.substeps,
+            value.map(|value| value.round().clamp(1.0, MAX_SUBSTEPS as f32) as u32),
+        ),
+        "time_scale" => update(
+            &mut settings.time_scale,
+            value.map(|value| value.clamp(0.05, 8.0)),
+        ),
+        "fixed_timestep" => update(&mut settings.fixed_timestep, flag),
+        "auto_randomize" => update(&mut settings.randomize, flag),
+        "params_change_per_frame" => update(&mut settings.params_change_per_frame, value),
+        "seed" => {
+            let changed = update(
+                &mut settings.seed,
+                args.first().and_then(|arg| match arg {
+                    OscArg::Int(seed) => Some(*seed as u32),
+                    OscArg::Long(seed) => Some(*seed as u32),
+                    _ => None,
+                }),
+            )?;
+            settings.state = SimState::Initialize;
+            Some(changed)
+        }
+        "reset" => update(&mut settings.state, Some(SimState::Initialize)),
+        "play" => update(&mut settings.state, Some(SimState::Playing)),
+        // Restarts are left to finish initializing
+        "pause" => match (settings.state, flag) {
+            (SimState::Initialize, _) => Some(false),
+            (SimState::Playing, Some(true) | None) => {
+                update(&mut settings.state, Some(SimState::Paused))
+            }
+            (SimState::Paused, Some(false) | None) => {
+                update(&mut settings.state, Some(SimState::Playing))
+            }
+            _ => Some(false),
+        },
+        _ => None,
+    }
+}
+
+fn apply_osc(
+    remote: Res<OscRemote>,
+    mut sim_params: ResMut<SimParams>,
+    mut settings: ResMut<SimSettings>,
+    mut agent_count: ResMut<AgentCount>,
+    rand_array: Res<RandArray>,
+    mut rng: ResMut<SimRng>,
+) {
+    let receiver = match &remote.receiver {
+        Some(receiver) => receiver,
+        None => return,
+    };
+    for message in receiver.try_iter() {
+        let path = match message.address.strip_prefix("/sim/") {
+            Some(path) => path,
+            None => {
+                warn!("Unknown OSC address {}", message.address);
+                continue;
+            }
+        };
+        let args = &message.args;
+
+        // Change detection decides what is uploaded and broadcast, so only real changes count
+        let applied = match path {
+            "randomize" => {
+                randomize_params(&mut sim_params, &rand_array, &mut rng.0);
+                Some(())
+            }
+            "agents" => args.first().and_then(OscArg::as_f32).map(|count| {
+                let count = (count.round() as u32).max(1);
+                if agent_count.0 != count {
+                    agent_count.0 = count;
+                    settings.state = SimState::Initialize;
+                }
+            }),
+            _ => match apply_setting(settings.bypass_change_detection(), path, args) {
+                Some(changed) => {
+                    if changed {
+                        settings.set_changed();
+                    }
+                    Some(())
+                }
+                None => {
+                    apply_param(sim_params.bypass_change_detection(), path, args).map(|changed| {
+                        if changed {
+                            sim_params.set_changed();
+                        }
+                    })
+                }
+            },
+        };
+        if applied.is_none() {
+            warn!(
+                "Unknown OSC address or arguments {} {:?}",
+                message.address, message.args
+            );
+        }
+    }
+}
+
+/// Every field that can be set, under the address it is set with
+fn state_messages(
+    params: &SimParams,
+    settings: &SimSettings,
+    agent_count: &AgentCount,
+) -> Vec<OscMessage> {
+    let mut messages = vec![
+        OscMessage::new("/sim/decay_rate", vec![OscArg::Float(params.decay_rate)]),
+        OscMessage::new(
+            "/sim/trail_weight",
+            vec![OscArg::Float(params.trail_weight)],
+        ),
+        OscMessage::new("/sim/blur_radius", vec![OscArg::Float(params.blur_radius)]),
+        OscMessage::new("/sim/blur_sigma", vec![OscArg::Float(params.blur_sigma)]),
+        OscMessage::new("/sim/food_weight", vec![OscArg::Float(params.food_weight)]),
+        OscMessage::new(
+            "/sim/palette_enabled",
+            vec![OscArg::Bool(params.palette_enabled)],
+        ),
+        OscMessage::new("/sim/blur_mask", color_args(params.blur_mask)),
+        OscMessage::new("/sim/substeps", vec![OscArg::Int(settings.substeps as i32)]),
+        OscMessage::new("/sim/time_scale", vec![OscArg::Float(settings.time_scale)]),
+        OscMessage::new(
+            "/sim/fixed_timestep",
+            vec![OscArg::Bool(settings.fixed_timestep)],
+        ),
+        OscMessage::new(
+            "/sim/auto_randomize",
+            vec![OscArg::Bool(settings.randomize)],
+        ),
+        OscMessage::new(
+            "/sim/params_change_per_frame",
+            vec![OscArg::Float(settings.params_change_per_frame)],
+        ),
+        OscMessage::new("/sim/seed", vec![OscArg::Int(settings.seed as i32)]),
+        OscMessage::new(
+            "/sim/pause",
+            vec![OscArg::Bool(settings.state == SimState::Paused)],
+        ),
+        OscMessage::new("/sim/agents", vec![OscArg::Int(agent_count.0 as i32)]),
+    ];
+    for (index, species) in params.species[..params.species_count as usize]
+        .iter()
+        .enumerate()
+    {
+        let address = |field: &str| format!("/sim/species/{}/{}", index + 1, field);
+        messages.extend([
+            OscMessage::new(address("color"), color_args(species.color)),
+            OscMessage::new(
+                address("move_speed"),
+                vec![OscArg::Float(species.move_speed)],
+            ),
+            OscMessage::new(
+                address("turn_speed"),
+                vec![OscArg::Float(species.turn_speed)],
+            ),
+            OscMessage::new(
+                address("sensor_angle_spacing"),
+                vec![OscArg::Float(species.sensor_angle_spacing)],
+            ),
+            OscMessage::new(
+                address("sensor_offset_distance"),
+                vec![OscArg::Float(species.sensor_offset_distance)],
+            ),
+            OscMessage::new(
+                address("sensor_size"),
+                vec![OscArg::Int(species.sensor_size as i32)],
+            ),
+        ]);
+    }
+    messages
+}
+
+/// Sends the fields whose values differ from what the target was last sent
+fn broadcast_osc(
+    mut remote: ResMut<OscRemote>,
+    sim_params: Res<SimParams>,
+    settings: Res<SimSettings>,
+    agent_count: Res<AgentCount>,
+) {
+    // A new target has not been sent anything yet
+    let resend = remote.sent.is_empty();
+    if !(resend || sim_params.is_changed() || settings.is_changed() || agent_count.is_changed()) {
+        return;
+    }
+    let target = match remote.target.parse::<SocketAddr>() {
+        Ok(target) => target,
+        Err(_) => return,
+    };
+    let remote = remote.as_mut();
+    let socket = match &remote.socket {
+        Some(socket) => socket,
+        None => return,
+    };
+
+    for message in state_messages(&sim_params, &settings, &agent_count) {
+        if remote.sent.get(&message.address) == Some(&message.args) {
+            continue;
+        }
+        if let Err(err) = socket.send_to(&message.encode(), target) {
+            remote.status = format!("Failed to send to {}: {}", target, err);
+            return;
+        }
+        remote.sent.insert(message.address, message.args);
+    }
+}
+
+fn ui_osc(
+    mut egui_context: ResMut<EguiContext>,
+    mut remote: ResMut<OscRemote>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    egui::Window::new("OSC").show(egui_context.ctx_mut(), |ui| {
+        ui.label("Send state to (ip:port)");
+        // A new target gets the whole state
+        if ui.text_edit_singleline(&mut remote.target).changed() {
+            remote.sent.clear();
+        }
+        if !remote.status.is_empty() {
+            ui.label(&remote.status);
+        }
+    });
+}
@@ -0,0 +1,242 @@
+//! Gradient palettes that the `color` pass maps trail intensity through for display.
+//!
+//! The gradient is baked into a 1D lookup texture of [`LUT_SIZE`] texels whenever it changes. The
+//! pass only runs the mapping with `SimParams::palette_enabled`, otherwise it shows the trail of
+//! every species in the species' color.
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+};
+use bevy_egui::{
+    egui::{self, color_picker::color_edit_button_srgba, Button, Checkbox, Color32, Sense, Slider},
+    EguiContext,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{EguiState, SimParams};
+
+pub const LUT_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    /// Trail intensity from 0 to 1 this color is shown at
+    pub position: f32,
+    #[serde(with = "crate::preset::color32")]
+    pub color: Color32,
+}
+
+const fn stop(position: f32, r: u8, g: u8, b: u8) -> GradientStop {
+    GradientStop {
+        position,
+        color: Color32::from_rgb(r, g, b),
+    }
+}
+
+const MAGMA: [GradientStop; 9] = [
+    stop(0.0, 0, 0, 4),
+    stop(0.125, 28, 16, 68),
+    stop(0.25, 79, 18, 123),
+    stop(0.375, 129, 37, 129),
+    stop(0.5, 181, 54, 122),
+    stop(0.625, 229, 80, 100),
+    stop(0.75, 251, 135, 97),
+    stop(0.875, 254, 194, 135),
+    stop(1.0, 252, 253, 191),
+];
+
+const VIRIDIS: [GradientStop; 9] = [
+    stop(0.0, 68, 1, 84),
+    stop(0.125, 71, 44, 122),
+    stop(0.25, 59, 81, 139),
+    stop(0.375, 44, 113, 142),
+    stop(0.5, 33, 144, 141),
+    stop(0.625, 39, 173, 129),
+    stop(0.75, 92, 200, 99),
+    stop(0.875, 170, 220, 50),
+    stop(1.0, 253, 231, 37),
+];
+
+const FIRE: [GradientStop; 5] = [
+    stop(0.0, 0, 0, 0),
+    stop(0.35, 200, 30, 0),
+    stop(0.65, 255, 150, 0),
+    stop(0.85, 255, 230, 80),
+    stop(1.0, 255, 255, 255),
+];
+
+const ICE: [GradientStop; 5] = [
+    stop(0.0, 0, 0, 0),
+    stop(0.35, 10, 40, 120),
+    stop(0.65, 40, 140, 220),
+    stop(0.85, 150, 220, 255),
+    stop(1.0, 255, 255, 255),
+];
+
+/// Stops sorted by position, saved in presets.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub stops: Vec<GradientStop>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            stops: MAGMA.to_vec(),
+        }
+    }
+}
+
+impl Palette {
+    pub const BUILTIN: [(&'static str, &'static [GradientStop]); 4] = [
+        ("Magma", &MAGMA),
+        ("Viridis", &VIRIDIS),
+        ("Fire", &FIRE),
+        ("Ice", &ICE),
+    ];
+
+    /// Puts the stops back in order, `sample` relies on it
+    pub fn sort(&mut self) {
+        self.stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+    }
+
+    /// The color at `position`, blended linearly between the stops around it
+    pub fn sample(&self, position: f32) -> Color32 {
+        let next = self.stops.iter().position(|stop| stop.position >= position);
+        let (low, high) = match next {
+            Some(0) => return self.stops[0].color,
+            Some(index) => (self.stops[index - 1], self.stops[index]),
+            None => return self.stops.last().map_or(Color32::BLACK, |stop| stop.color),
+        };
+        let t = (position - low.position) / (high.position - low.position).max(0.00001);
+        let [r, g, b, a] = [0, 1, 2, 3].map(|channel| {
+            let low = low.color.to_array()[channel] as f32;
+            let high = high.color.to_array()[channel] as f32;
+            (low + (high - low) * t).round() as u8
+        });
+        Color32::from_rgba_premultiplied(r, g, b, a)
+    }
+
+    /// `Rgba8Unorm` texels of the lookup texture
+    pub fn to_lut(&self) -> Vec<u8> {
+        (0..LUT_SIZE)
+            .flat_map(|texel| self.sample(texel as f32 / (LUT_SIZE - 1) as f32).to_array())
+            .collect()
+    }
+
+    pub fn lut_image(&self) -> Image {
+        let mut image = Image::new(
+            Extent3d {
+                width: LUT_SIZE,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D1,
+            self.to_lut(),
+            TextureFormat::Rgba8Unorm,
+        );
+        image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+        image
+    }
+}
+
+/// The lookup texture bound to the `color` pass.
+#[derive(Clone, Deref, ExtractResource, Resource)]
+pub struct PaletteLut(pub Handle<Image>);
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Palette>()
+            .add_system(update_palette_lut);
+    }
+}
+
+fn update_palette_lut(
+    palette: Res<Palette>,
+    lut: Res<PaletteLut>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !palette.is_changed() {
+        return;
+    }
+    if let Some(image) = images.get_mut(&lut) {
+        image.data = palette.to_lut();
+    }
+}
+
+/// The gradient editor, edits only touch the palette when something changed
+pub fn ui_palette(
+    mut egui_context: ResMut<EguiContext>,
+    mut palette: ResMut<Palette>,
+    mut sim_params: ResMut<SimParams>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    let mut edited = palette.clone();
+    let mut enabled = sim_params.palette_enabled;
+    egui::Window::new("Palette").show(egui_context.ctx_mut(), |ui| {
+        ui.add(Checkbox::new(&mut enabled, "Map Intensity to Palette"));
+        ui.horizontal(|ui| {
+            for (name, stops) in Palette::BUILTIN {
+                if ui.add(Button::new(name)).clicked() {
+                    edited.stops = stops.to_vec();
+                }
+            }
+        });
+
+        // Preview of the gradient
+        let (rect, _) =
+            ui.allocate_exact_size(egui::vec2(ui.available_width(), 20.0), Sense::hover());
+        let segments = 64;
+        for segment in 0..segments {
+            let left = rect.left() + rect.width() * segment as f32 / segments as f32;
+            let right = rect.left() + rect.width() * (segment + 1) as f32 / segments as f32;
+            ui.painter().rect_filled(
+                egui::Rect::from_x_y_ranges(left..=right, rect.y_range()),
+                0.0,
+                edited.sample((segment as f32 + 0.5) / segments as f32),
+            );
+        }
+
+        let mut remove = None;
+        for (index, stop) in edited.stops.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                color_edit_button_srgba(ui, &mut stop.color, egui::color_picker::Alpha::Opaque);
+                ui.add(Slider::new(&mut stop.position, 0.0..=1.0));
+                if ui.add(Button::new("-")).clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            if edited.stops.len() > 2 {
+                edited.stops.remove(index);
+            }
+        }
+        if ui.add(Button::new("+")).clicked() {
+            let color = edited.sample(0.5);
+            edited.stops.push(GradientStop {
+                position: 0.5,
+                color,
+            });
+        }
+        // Sorting mid-drag would hand the slider to another stop
+        if !ui.ctx().is_using_pointer() {
+            edited.sort();
+        }
+    });
+
+    if edited != *palette {
+        *palette = edited;
+    }
+    if enabled != sim_params.palette_enabled {
+        sim_params.palette_enabled = enabled;
+    }
+}
@@ -0,0 +1,124 @@
+//! The `SimParams` fields that MIDI controls and audio can drive.
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use serde::{Deserialize, Serialize};
+
+use crate::SimParams;
+
+/// A `SimParams` field, species fields are per species index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParamTarget {
+    DecayRate,
+    TrailWeight,
+    BlurRadius,
+    BlurSigma,
+    FoodWeight,
+    MoveSpeed(usize),
+    TurnSpeed(usize),
+    SensorAngleSpacing(usize),
+    SensorOffsetDistance(usize),
+}
+
+impl ParamTarget {
+    /// Every target, with the species fields of the first `species_count` species
+    pub fn all(species_count: usize) -> Vec<Self> {
+        let mut targets = vec![
+            Self::DecayRate,
+            Self::TrailWeight,
+            Self::BlurRadius,
+            Self::BlurSigma,
+            Self::FoodWeight,
+        ];
+        for index in 0..species_count {
+            targets.extend([
+                Self::MoveSpeed(index),
+                Self::TurnSpeed(index),
+                Self::SensorAngleSpacing(index),
+                Self::SensorOffsetDistance(index),
+            ]);
+        }
+        targets
+    }
+
+    /// The range of the field's slider
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            Self::DecayRate => (0.01, 5.0),
+            Self::TrailWeight => (0.1, 1.2),
+            Self::BlurRadius => (0.0, 16.0),
+            Self::BlurSigma => (0.1, 10.0),
+            Self::FoodWeight => (0.0, 10.0),
+            Self::MoveSpeed(_) => (10.0, 1000.0),
+            Self::TurnSpeed(_) => (0.1, 100.0),
+            Self::SensorAngleSpacing(_) => (1.0, 360.0),
+            Self::SensorOffsetDistance(_) => (1.0, 1000.0),
+        }
+    }
+
+    pub fn field<'a>(&self, params: &'a mut SimParams) -> Option<&'a mut f32> {
+        // Indices come from saved files, so they are not trusted
+        match *self {
+            Self::DecayRate => Some(&mut params.decay_rate),
+            Self::TrailWeight => Some(&mut params.trail_weight),
+            Self::BlurRadius => Some(&mut params.blur_radius),
+            Self::BlurSigma => Some(&mut params.blur_sigma),
+            Self::FoodWeight => Some(&mut params.food_weight),
+            Self::MoveSpeed(index) => params
+                .species
+                .get_mut(index)
+                .map(|species| &mut species.move_speed),
+            Self::TurnSpeed(index) => params
+                .species
+                .get_mut(index)
+                .map(|species| &mut species.turn_speed),
+            Self::SensorAngleSpacing(index) => params
+                .species
+                .get_mut(index)
+                .map(|species| &mut species.sensor_angle_spacing),
+            Self::SensorOffsetDistance(index) => params
+                .species
+                .get_mut(index)
+                .map(|species| &mut species.sensor_offset_distance),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Self::DecayRate => "decay_rate".to_string(),
+            Self::TrailWeight => "trail_weight".to_string(),
+            Self::BlurRadius => "blur_radius".to_string(),
+            Self::BlurSigma => "blur_sigma".to_string(),
+            Self::FoodWeight => "food_weight".to_string(),
+            Self::MoveSpeed(index) => format!("move_speed {}", index + 1),
+            Self::TurnSpeed(index) => format!("turn_speed {}", index + 1),
+            Self::SensorAngleSpacing(index) => format!("sensor_angle_spacing {}", index + 1),
+            Self::SensorOffsetDistance(index) => format!("sensor_offset_distance {}", index + 1),
+        }
+    }
+}
+
+/// Amounts added to `SimParams` fields only as they are exported, so modulation never ends up in
+/// the values that are edited, saved in presets or written into screenshots.
+#[derive(Debug, Clone, Default, PartialEq, Resource, ExtractResource)]
+pub struct ParamOffsets(Vec<(ParamTarget, f32)>);
+
+impl ParamOffsets {
+    /// Offsets of the same target add up
+    pub fn add(&mut self, target: ParamTarget, amount: f32) {
+        match self.0.iter_mut().find(|(existing, _)| *existing == target) {
+            Some((_, total)) => *total += amount,
+            None => self.0.push((target, amount)),
+        }
+    }
+
+    /// The params with the offsets added, each offset field clamped to its target's range
+    pub fn apply(&self, params: &SimParams) -> SimParams {
+        let mut params = *params;
+        for (target, amount) in self.0.iter() {
+            let (min, max) = target.range();
+            if let Some(field) = target.field(&mut params) {
+                *field = (*field + amount).clamp(min, max);
+            }
+        }
+        params
+    }
+}
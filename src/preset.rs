@@ -0,0 +1,364 @@
+//! Simulation presets stored as RON files under `assets/presets`.
+//!
+//! Presets are loaded through the asset server, so editing the active preset on disk applies it
+//! live through the `filesystem_watcher`.
+use bevy::{
+    asset::{AssetLoader, FileAssetIo, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_egui::egui::Color32;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    cli::LaunchOptions, food_map::FoodMap, palette::Palette, spawn_mask::SpawnMask,
+    timeline::Timeline, RandArray, RandInfo, RandParams, RandomizableParams, SimParams,
+    SimSettings, SimState, MAX_SPECIES,
+};
+
+pub const DEFAULT_PRESET: &str = "presets/default.preset.ron";
+
+/// `#[serde(with = "preset::color32")]` for egui colors, stored as `(r, g, b, a)`
+pub mod color32 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(color: &Color32, serializer: S) -> Result<S::Ok, S::Error> {
+        color.to_array().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color32, D::Error> {
+        let [r, g, b, a] = <[u8; 4]>::deserialize(deserializer)?;
+        Ok(Color32::from_rgba_premultiplied(r, g, b, a))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "6f0b7d54-2d2e-4c8a-9a43-7e0c1f3b5a21"]
+pub struct Preset {
+    pub params: SimParams,
+    pub randomize: bool,
+    pub params_change_per_frame: f32,
+    pub randomizer: Vec<RandRange>,
+    /// Presets without a seed keep the one the simulation is running with
+    #[serde(default)]
+    pub seed: Option<u32>,
+    /// Image for `ImageMask` species, relative to `assets`
+    #[serde(default)]
+    pub spawn_mask: Option<String>,
+    /// Image for the food map, relative to `assets`
+    #[serde(default)]
+    pub food_map: Option<String>,
+    /// Presets without a palette keep the one already in use
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    /// Presets without a timeline keep the one already in use
+    #[serde(default)]
+    pub timeline: Option<Timeline>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RandRange {
+    pub param: RandomizableParams,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Preset {
+    /// Parses a preset file, keeping the species count to what the simulation can run and the
+    /// palette stops in order, as hand edited files may not have them
+    pub fn from_ron(bytes: &[u8]) -> Result<Self, ron::error::SpannedError> {
+        let mut preset: Self = ron::de::from_bytes(bytes)?;
+        preset.params.species_count = preset.params.species_count.clamp(1, MAX_SPECIES as u32);
+        if let Some(palette) = &mut preset.palette {
+            palette.sort();
+        }
+        Ok(preset)
+    }
+
+    pub fn capture(
+        sim_params: &SimParams,
+        settings: &SimSettings,
+        rand_array: &RandArray,
+        spawn_mask: &SpawnMask,
+        food_map: &FoodMap,
+        palette: &Palette,
+        timeline: &Timeline,
+    ) -> Self {
+        Self {
+            params: *sim_params,
+            randomize: settings.randomize,
+            params_change_per_frame: settings.params_change_per_frame,
+            randomizer: rand_array
+                .array
+                .iter()
+                .map(|info| RandRange {
+                    param: info.index,
+                    start: info.params.start,
+                    end: info.params.end,
+                })
+                .collect(),
+            seed: Some(settings.seed),
+            spawn_mask: spawn_mask.handle.as_ref().map(|_| spawn_mask.path.clone()),
+            food_map: food_map.handle.as_ref().map(|_| food_map.path.clone()),
+            palette: Some(palette.clone()),
+            timeline: Some(timeline.clone()),
+        }
+    }
+
+    /// Keeps the size and timing of the running simulation, and restarts it only when the
+    /// species, their spawn modes or the seed change.
+    pub fn apply(
+        &self,
+        sim_params: &mut SimParams,
+        settings: &mut SimSettings,
+        rand_array: &mut RandArray,
+        palette: &mut Palette,
+        timeline: &mut Timeline,
+    ) {
+        let SimParams {
+            width,
+            height,
+            time,
+            delta,
+            salt,
+            ..
+        } = *sim_params;
+
+        let respawn = matches!(self.seed, Some(seed) if seed != settings.seed)
+            || sim_params.species_count != self.params.species_count
+            || sim_params
+                .species
+                .iter()
+                .zip(self.params.species.iter())
+                .any(|(current, preset)| current.mode != preset.mode);
+
+        *sim_params = SimParams {
+            width,
+            height,
+            time,
+            delta,
+            salt,
+            ..self.params
+        };
+
+        settings.randomize = self.randomize;
+        settings.params_change_per_frame = self.params_change_per_frame;
+        if let Some(seed) = self.seed {
+            settings.seed = seed;
+        }
+        if let Some(preset_palette) = &self.palette {
+            if preset_palette != palette {
+                *palette = preset_palette.clone();
+            }
+        }
+        if let Some(preset_timeline) = &self.timeline {
+            if preset_timeline.differs(timeline) {
+                timeline.load(preset_timeline);
+            }
+        }
+        if respawn {
+            settings.state = SimState::Initialize;
+        }
+
+        let mut array = Vec::with_capacity(self.randomizer.len());
+        for range in self.randomizer.iter() {
+            // Modifiers only exist in code, keep the one already set up for this param
+            let modifier = rand_array
+                .array
+                .iter()
+                .find(|info| info.index == range.param)
+                .and_then(|info| info.params.modifier);
+            array.push(RandInfo::new(
+                range.param,
+                RandParams {
+                    modifier,
+                    start: range.start,
+                    end: range.end,
+                },
+            ));
+        }
+        rand_array.array = array;
+    }
+
+    /// Loads the spawn mask and food map the preset names, unless they are already loaded
+    pub fn load_images(
+        &self,
+        spawn_mask: &mut SpawnMask,
+        food_map: &mut FoodMap,
+        asset_server: &AssetServer,
+    ) {
+        if let Some(path) = &self.spawn_mask {
+            if *path != spawn_mask.path {
+                spawn_mask.load(asset_server, path);
+            }
+        }
+        if let Some(path) = &self.food_map {
+            if *path != food_map.path {
+                food_map.load(asset_server, path);
+            }
+        }
+    }
+
+    /// Reads a preset outside the asset server, for the CPU fallback
+    pub fn read(path: &str) -> Result<Self, String> {
+        let file = FileAssetIo::get_base_path().join("assets").join(path);
+        let text =
+            std::fs::read_to_string(&file).map_err(|err| format!("{}: {}", file.display(), err))?;
+        Self::from_ron(text.as_bytes()).map_err(|err| format!("{}: {}", file.display(), err))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = FileAssetIo::get_base_path().join("assets").join(path);
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(&file, text).map_err(|err| format!("{}: {}", file.display(), err))
+    }
+}
+
+#[derive(Default)]
+pub struct PresetLoader;
+
+impl AssetLoader for PresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let preset = Preset::from_ron(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(preset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["preset.ron"]
+    }
+}
+
+/// The preset that gets applied whenever its file is (re)loaded.
+#[derive(Resource)]
+pub struct ActivePreset {
+    pub handle: Handle<Preset>,
+    pub path: String,
+}
+
+impl ActivePreset {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &str) {
+        self.handle = asset_server.load(path);
+        self.path = path.to_string();
+    }
+}
+
+pub struct PresetPlugin;
+
+impl Plugin for PresetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Preset>()
+            .init_asset_loader::<PresetLoader>()
+            .add_system(apply_active_preset);
+
+        let path = app.world.get_resource::<LaunchOptions>().map_or_else(
+            || DEFAULT_PRESET.to_string(),
+            |options| options.preset.clone(),
+        );
+        let handle = app.world.resource::<AssetServer>().load(&path);
+        app.insert_resource(ActivePreset { handle, path });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_active_preset(
+    mut events: EventReader<AssetEvent<Preset>>,
+    presets: Res<Assets<Preset>>,
+    active: Res<ActivePreset>,
+    mut sim_params: ResMut<SimParams>,
+    mut settings: ResMut<SimSettings>,
+    mut rand_array: ResMut<RandArray>,
+    mut spawn_mask: ResMut<SpawnMask>,
+    mut food_map: ResMut<FoodMap>,
+    mut palette: ResMut<Palette>,
+    mut timeline: ResMut<Timeline>,
+    asset_server: Res<AssetServer>,
+) {
+    let mut reloaded = active.is_changed();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                reloaded |= *handle == active.handle
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    if !reloaded {
+        return;
+    }
+    if let Some(preset) = presets.get(&active.handle) {
+        preset.apply(
+            &mut sim_params,
+            &mut settings,
+            &mut rand_array,
+            &mut palette,
+            &mut timeline,
+        );
+        preset.load_images(&mut spawn_mask, &mut food_map, &asset_server);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset() -> Preset {
+        Preset {
+            params: crate::default_sim_params(64, 64),
+            randomize: false,
+            params_change_per_frame: 0.0,
+            randomizer: Vec::new(),
+            seed: None,
+            spawn_mask: None,
+            food_map: None,
+            palette: None,
+            timeline: None,
+        }
+    }
+
+    fn to_ron(preset: &Preset) -> String {
+        ron::ser::to_string_pretty(preset, ron::ser::PrettyConfig::default()).unwrap()
+    }
+
+    fn preset_with_species_count(species_count: u32) -> String {
+        let text = to_ron(&preset());
+        assert!(text.contains("species_count: 1,"));
+        text.replace(
+            "species_count: 1,",
+            &format!("species_count: {},", species_count),
+        )
+    }
+
+    #[test]
+    fn species_count_is_clamped_on_load() {
+        for (species_count, loaded) in [(0, 1), (3, 3), (9, MAX_SPECIES as u32)] {
+            let text = preset_with_species_count(species_count);
+            let preset = Preset::from_ron(text.as_bytes()).unwrap();
+            assert_eq!(preset.params.species_count, loaded);
+        }
+    }
+
+    #[test]
+    fn palette_stops_are_sorted_on_load() {
+        let mut palette = Palette::default();
+        palette.stops.reverse();
+        let text = to_ron(&Preset {
+            palette: Some(palette),
+            ..preset()
+        });
+        let loaded = Preset::from_ron(text.as_bytes()).unwrap().palette.unwrap();
+        assert_eq!(loaded, Palette::default());
+    }
+}
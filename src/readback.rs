@@ -0,0 +1,272 @@
+//! Copies the displayed trail back to the CPU through a ring of staging buffers.
+//!
+//! Copies are mapped asynchronously and handed to the main world in the order they were rendered,
+//! a frame or two later. Only when every staging buffer is still in flight does the render thread
+//! wait for the oldest one, so no frame is dropped.
+use std::{
+    collections::VecDeque,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        RenderApp, RenderStage,
+    },
+};
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{DisplayImage, SimSettings, SimState};
+
+/// Staging buffers that can be in flight before the render thread waits for the oldest
+const STAGING_BUFFERS: usize = 3;
+
+/// Set `enabled` in the main world to copy `DisplayImage` back every frame.
+#[derive(Debug, Clone, Copy, Default, Resource, ExtractResource)]
+pub struct FrameCapture {
+    pub enabled: bool,
+    /// Wait for every copy in the frame it was made, so frames arrive in lockstep with the
+    /// simulation
+    pub wait: bool,
+}
+
+/// Tightly packed `Rgba8Unorm` pixels of one captured frame.
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    /// The state the frame was rendered in
+    pub state: SimState,
+    pub data: Vec<u8>,
+}
+
+#[derive(Resource, Deref)]
+pub struct CapturedFrames(Receiver<CapturedFrame>);
+
+#[derive(Resource, Deref)]
+struct FrameSender(Sender<CapturedFrame>);
+
+struct StagingBuffer {
+    buffer: Buffer,
+    /// Set from the map callback once the copy can be read
+    mapped: Arc<AtomicBool>,
+}
+
+#[derive(Resource, Default)]
+struct FrameReadback {
+    buffers: Vec<StagingBuffer>,
+    /// Buffers that hold a copy, oldest first, with the state the copy was rendered in
+    in_flight: VecDeque<(usize, SimState)>,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    /// The buffer `ReadbackNode` copies into this frame
+    copy_into: Option<usize>,
+}
+
+impl FrameReadback {
+    /// Sends the mapped copies at the front of the queue, in the order they were made
+    fn send_mapped(&mut self, sender: &Sender<CapturedFrame>) {
+        while let Some(&(index, state)) = self.in_flight.front() {
+            let staging = &self.buffers[index];
+            if !staging.mapped.load(Ordering::Acquire) {
+                return;
+            }
+            self.in_flight.pop_front();
+
+            let slice = staging.buffer.slice(..);
+            let row_bytes = self.width as usize * 4;
+            let mut data = Vec::with_capacity(row_bytes * self.height as usize);
+            for row in slice
+                .get_mapped_range()
+                .chunks(self.padded_bytes_per_row as usize)
+            {
+                data.extend_from_slice(&row[..row_bytes]);
+            }
+            staging.buffer.unmap();
+            staging.mapped.store(false, Ordering::Release);
+
+            let _ = sender.send(CapturedFrame {
+                width: self.width,
+                height: self.height,
+                state,
+                data,
+            });
+        }
+    }
+
+    fn free_buffer(&self) -> Option<usize> {
+        (0..self.buffers.len()).find(|index| {
+            !self
+                .in_flight
+                .iter()
+                .any(|(in_flight, _)| in_flight == index)
+        })
+    }
+}
+
+pub struct FrameReadbackPlugin;
+
+impl Plugin for FrameReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        app.init_resource::<FrameCapture>()
+            .insert_resource(CapturedFrames(receiver))
+            .add_plugin(ExtractResourcePlugin::<FrameCapture>::default());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(FrameSender(sender))
+            .init_resource::<FrameReadback>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_readback)
+            .add_system_to_stage(RenderStage::Cleanup, map_readback);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("readback", ReadbackNode);
+        render_graph.add_node_edge("color", "readback").unwrap();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_readback(
+    mut readback: ResMut<FrameReadback>,
+    capture: Res<FrameCapture>,
+    settings: Res<SimSettings>,
+    sender: Res<FrameSender>,
+    display_image: Res<DisplayImage>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<crate::GameOfLifePipeline>,
+    pass_pipelines: Res<crate::compute_pass::ComputePassPipelines>,
+) {
+    readback.copy_into = None;
+
+    let gpu_image = match gpu_images.get(&display_image.0) {
+        Some(gpu_image) => gpu_image,
+        None => return,
+    };
+    if !capture.enabled
+        || !pipeline.is_ready(&pipeline_cache)
+        || !pass_pipelines.is_ready(&pipeline_cache)
+    {
+        return;
+    }
+
+    let width = gpu_image.size.x as u32;
+    let height = gpu_image.size.y as u32;
+    if readback.width != width || readback.height != height {
+        // Copies of the old size are still sent, the buffers are recreated once they are
+        render_device.poll(wgpu::Maintain::Wait);
+        readback.send_mapped(&sender);
+        readback.buffers.clear();
+        readback.in_flight.clear();
+        readback.width = width;
+        readback.height = height;
+        readback.padded_bytes_per_row =
+            RenderDevice::align_copy_bytes_per_row(width as usize * 4) as u32;
+    }
+
+    let index = match readback.free_buffer() {
+        Some(index) => index,
+        None if readback.buffers.len() < STAGING_BUFFERS => {
+            let size = (readback.padded_bytes_per_row * height) as u64;
+            readback.buffers.push(StagingBuffer {
+                buffer: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("Readback buffer"),
+                    size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                mapped: Arc::new(AtomicBool::new(false)),
+            });
+            readback.buffers.len() - 1
+        }
+        None => {
+            // Every buffer is in flight, wait for the oldest rather than drop a frame
+            render_device.poll(wgpu::Maintain::Wait);
+            readback.send_mapped(&sender);
+            match readback.free_buffer() {
+                Some(index) => index,
+                None => return,
+            }
+        }
+    };
+    readback.in_flight.push_back((index, settings.state));
+    readback.copy_into = Some(index);
+}
+
+/// Maps the copy queued by `ReadbackNode` and hands every finished copy to the main world
+fn map_readback(
+    mut readback: ResMut<FrameReadback>,
+    capture: Res<FrameCapture>,
+    render_device: Res<RenderDevice>,
+    sender: Res<FrameSender>,
+) {
+    if let Some(index) = readback.copy_into {
+        let staging = &readback.buffers[index];
+        let mapped = staging.mapped.clone();
+        render_device.map_buffer(&staging.buffer.slice(..), MapMode::Read, move |result| {
+            if result.is_ok() {
+                mapped.store(true, Ordering::Release);
+            }
+        });
+    }
+    if readback.in_flight.is_empty() {
+        return;
+    }
+
+    render_device.poll(if capture.wait {
+        wgpu::Maintain::Wait
+    } else {
+        wgpu::Maintain::Poll
+    });
+    readback.send_mapped(&sender);
+}
+
+struct ReadbackNode;
+
+impl render_graph::Node for ReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let readback = world.resource::<FrameReadback>();
+        let buffer = match readback.copy_into {
+            Some(index) => &readback.buffers[index].buffer,
+            None => return Ok(()),
+        };
+        let image = &world.resource::<DisplayImage>().0;
+        let gpu_image = &world.resource::<RenderAssets<Image>>()[image];
+
+        render_context.command_encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(readback.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: readback.width,
+                height: readback.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+//! Records the simulation to a numbered PNG sequence or a Y4M video.
+//!
+//! While recording, the simulation advances by exactly one video frame per rendered frame, so the
+//! clip plays back at `fps` no matter how long frames took to render. Frames are encoded on their
+//! own thread, the render thread only copies them back. When the encoder falls more than
+//! [`ENCODER_QUEUE`] frames behind, the simulation waits for it instead of dropping frames.
+//!
+//! Captured frames are also handed to a pending [`Screenshot`] from here, as both read them back
+//! through the same channel.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Button, ComboBox, DragValue},
+    EguiContext,
+};
+use crossbeam_channel::Sender;
+
+use crate::{
+    headless::save_png,
+    readback::{CapturedFrame, CapturedFrames, FrameCapture},
+    screenshot::{request_screenshot, Screenshot, SCREENSHOT_KEY},
+    EguiState, SimSettings, SimState,
+};
+
+/// Frames that can wait for the encoder, each one a full copy of the display
+const ENCODER_QUEUE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    PngSequence,
+    /// Uncompressed 4:4:4 YUV, which ffmpeg and most players read directly
+    Y4m,
+}
+
+#[derive(Resource)]
+pub struct Recorder {
+    pub format: RecordFormat,
+    /// Directory recordings are written to, each one gets its own name inside it
+    pub output: String,
+    pub fps: f32,
+    pub recording: bool,
+    /// Frames sent to the encoder in the current recording
+    pub frames: u32,
+    pub status: String,
+    /// Sends frames to the encoder thread, which exits once this is dropped
+    encoder: Option<Sender<CapturedFrame>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            format: RecordFormat::PngSequence,
+            output: "recordings".to_string(),
+            fps: 60.0,
+            recording: false,
+            frames: 0,
+            status: String::new(),
+            encoder: None,
+        }
+    }
+}
+
+fn spawn_encoder(
+    format: RecordFormat,
+    path: PathBuf,
+    fps: f32,
+) -> std::io::Result<Sender<CapturedFrame>> {
+    let (sender, receiver) = crossbeam_channel::bounded::<CapturedFrame>(ENCODER_QUEUE);
+    match format {
+        RecordFormat::PngSequence => {
+            std::fs::create_dir_all(&path)?;
+            std::thread::spawn(move || {
+                for (index, frame) in receiver.iter().enumerate() {
+                    let frame_path = path.join(format!("frame_{:05}.png", index));
+                    if let Err(err) = save_png(&frame_path, frame.width, frame.height, &frame.data)
+                    {
+                        error!("{}", err);
+                        return;
+                    }
+                }
+            });
+        }
+        RecordFormat::Y4m => {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let mut file = BufWriter::new(File::create(&path)?);
+            std::thread::spawn(move || {
+                let mut header_written = false;
+                for frame in receiver.iter() {
+                    let result = if header_written {
+                        Ok(())
+                    } else {
+                        header_written = true;
+                        write_y4m_header(&mut file, frame.width, frame.height, fps)
+                    }
+                    .and_then(|_| write_y4m_frame(&mut file, &frame));
+                    if let Err(err) = result {
+                        error!("Failed to write {}: {}", path.display(), err);
+                        return;
+                    }
+                }
+                if let Err(err) = file.flush() {
+                    error!("Failed to write {}: {}", path.display(), err);
+                }
+            });
+        }
+    }
+    Ok(sender)
+}
+
+fn write_y4m_header(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    fps: f32,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444",
+        width,
+        height,
+        (fps * 1000.0).round() as u32
+    )
+}
+
+/// Converts the RGBA pixels to studio range BT.601 planes
+fn write_y4m_frame(out: &mut impl Write, frame: &CapturedFrame) -> std::io::Result<()> {
+    let pixels = (frame.width * frame.height) as usize;
+    let mut planes = vec![0u8; pixels * 3];
+    for (index, pixel) in frame.data.chunks_exact(4).enumerate() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.0);
+        planes[index] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        planes[pixels + index] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        planes[pixels * 2 + index] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+    out.write_all(b"FRAME\n")?;
+    out.write_all(&planes)
+}
+
+fn recording_path(output: &str, format: RecordFormat) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let name = format!("recording_{}", seconds);
+    match format {
+        RecordFormat::PngSequence => Path::new(output).join(name),
+        RecordFormat::Y4m => Path::new(output).join(name + ".y4m"),
+    }
+}
+
+pub struct RecordPlugin;
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Recorder>()
+            .add_system(ui_recorder)
+            .add_system(record_frames.after(ui_recorder).after(request_screenshot));
+    }
+}
+
+/// Starts and stops the encoder as `recording` toggles, and keeps the frame capture running
+/// while there is a recording or a screenshot to capture
+fn record_frames(
+    mut recorder: ResMut<Recorder>,
+    mut screenshot: ResMut<Screenshot>,
+    mut capture: ResMut<FrameCapture>,
+    mut settings: ResMut<SimSettings>,
+    frames: Res<CapturedFrames>,
+) {
+    if recorder.recording && recorder.encoder.is_none() {
+        let path = recording_path(&recorder.output, recorder.format);
+        match spawn_encoder(recorder.format, path.clone(), recorder.fps) {
+            Ok(encoder) => {
+                recorder.encoder = Some(encoder);
+                recorder.frames = 0;
+                recorder.status = format!("Recording to {}", path.display());
+                // Every recorded frame advances exactly one frame of the video
+                settings.locked_frame_time = Some(1.0 / recorder.fps);
+            }
+            Err(err) => {
+                recorder.recording = false;
+                recorder.status = format!("{}: {}", path.display(), err);
+            }
+        }
+    }
+
+    // Copies still in flight when the recording stops are dropped
+    for frame in frames.try_iter() {
+        screenshot.save(&frame);
+        let encoder = match &recorder.encoder {
+            Some(encoder) => encoder,
+            None => continue,
+        };
+        // Paused frames would only repeat the last one
+        if frame.state == SimState::Playing {
+            // Blocks while the queue is full, only fails once the encoder gave up on an error
+            if encoder.send(frame).is_err() {
+                recorder.recording = false;
+                recorder.encoder = None;
+                settings.locked_frame_time = None;
+                recorder.status = format!(
+                    "Recording stopped after {} frames, the encoder failed",
+                    recorder.frames
+                );
+                continue;
+            }
+            recorder.frames += 1;
+        }
+    }
+
+    if !recorder.recording {
+        // Dropping the sender lets the encoder finish the frames it has and exit
+        if recorder.encoder.take().is_some() {
+            settings.locked_frame_time = None;
+            recorder.status = format!("Recorded {} frames", recorder.frames);
+        }
+    }
+
+    let enabled = recorder.encoder.is_some() || screenshot.is_pending();
+    if capture.enabled != enabled {
+        capture.enabled = enabled;
+    }
+}
+
+fn ui_recorder(
+    mut egui_context: ResMut<EguiContext>,
+    mut recorder: ResMut<Recorder>,
+    mut screenshot: ResMut<Screenshot>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    egui::Window::new("Record").show(egui_context.ctx_mut(), |ui| {
+        ui.add_enabled_ui(!recorder.recording, |ui| {
+            ComboBox::from_label("Format")
+                .selected_text(match recorder.format {
+                    RecordFormat::PngSequence => "PNG Sequence",
+                    RecordFormat::Y4m => "Y4M",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut recorder.format,
+                        RecordFormat::PngSequence,
+                        "PNG Sequence",
+                    );
+                    ui.selectable_value(&mut recorder.format, RecordFormat::Y4m, "Y4M");
+                });
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut recorder.fps).clamp_range(1.0..=240.0));
+                ui.label("fps");
+            });
+            ui.text_edit_singleline(&mut recorder.output);
+        });
+
+        let label = if recorder.recording {
+            format!("Stop ({} frames)", recorder.frames)
+        } else {
+            "Record".to_string()
+        };
+        if ui.add(Button::new(label)).clicked() {
+            recorder.recording = !recorder.recording;
+        }
+        if !recorder.status.is_empty() {
+            ui.label(&recorder.status);
+        }
+
+        ui.separator();
+        ui.text_edit_singleline(&mut screenshot.output);
+        if ui
+            .add(Button::new(format!("Screenshot ({:?})", SCREENSHOT_KEY)))
+            .clicked()
+        {
+            screenshot.requested = true;
+        }
+        if !screenshot.status.is_empty() {
+            ui.label(&screenshot.status);
+        }
+    });
+}
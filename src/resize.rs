@@ -0,0 +1,320 @@
+//! Follows the window size, reallocating every texture the simulation draws into.
+//!
+//! The trail textures are replaced rather than resized, so the old front one can be read once
+//! more. The `rescale` kernel samples it into both new textures, unless the trail is cleared on
+//! resize, and `clamp_agents` moves agents left outside back in at the edge.
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        RenderApp, RenderStage,
+    },
+    window::{WindowId, WindowResized},
+};
+
+use crate::{
+    food_map::FoodMap, trail_image, window_texture_size, DisplayImage, GameOfLifeImageSecond,
+    SimMeta, SimParams, SimSettings, TrailImages, TrailSprite, GAME_WORKGROUP_SIZE, TRAIL_FORMAT,
+    WORKGROUP_SIZE,
+};
+
+#[derive(Resource, Clone, ExtractResource)]
+pub struct TrailResize {
+    /// Rescale the trail into the resized textures, otherwise it starts out cleared
+    pub rescale: bool,
+    /// The front trail texture from before the resize, kept for the frame it is read in
+    from: Option<Handle<Image>>,
+}
+
+impl Default for TrailResize {
+    fn default() -> Self {
+        Self {
+            rescale: true,
+            from: None,
+        }
+    }
+}
+
+pub struct ResizePlugin;
+
+impl Plugin for ResizePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TrailResize>()
+            .add_plugin(ExtractResourcePlugin::<TrailResize>::default())
+            .add_system(resize_simulation);
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .init_resource::<ResizePipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_resize_bind_group);
+
+        // Runs before the first pass of the chain reads the new textures
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("resize", ResizeNode);
+        render_graph.add_node_edge("resize", "decay").unwrap();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resize_simulation(
+    mut events: EventReader<WindowResized>,
+    mut resize: ResMut<TrailResize>,
+    mut trail_images: ResMut<TrailImages>,
+    display_image: Res<DisplayImage>,
+    image_second: Res<GameOfLifeImageSecond>,
+    mut images: ResMut<Assets<Image>>,
+    mut sim_params: ResMut<SimParams>,
+    mut settings: ResMut<SimSettings>,
+    mut food_map: ResMut<FoodMap>,
+    mut sprites: Query<&mut Sprite, With<TrailSprite>>,
+) {
+    // The frame that resized has read the old texture, so it can be dropped
+    if resize.from.is_some() {
+        resize.from = None;
+    }
+
+    let (width, height) = match events
+        .iter()
+        .rev()
+        .find(|event| event.id == WindowId::primary())
+    {
+        Some(event) => window_texture_size(event.width, event.height),
+        None => return,
+    };
+    if (width, height) == (settings.width, settings.height) {
+        return;
+    }
+
+    resize.from = Some(trail_images.latest().clone());
+    *trail_images = TrailImages {
+        images: [
+            images.add(trail_image(width, height)),
+            images.add(trail_image(width, height)),
+        ],
+        latest: 0,
+        passes_running: trail_images.passes_running,
+    };
+
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    for handle in [&display_image.0, &image_second.0] {
+        if let Some(image) = images.get_mut(handle) {
+            image.resize(size);
+        }
+    }
+    // Fits the food map into the resized texture again
+    food_map.set_changed();
+
+    sim_params.width = width;
+    sim_params.height = height;
+    settings.width = width;
+    settings.height = height;
+    for mut sprite in sprites.iter_mut() {
+        sprite.custom_size = Some(Vec2::new(width as f32, height as f32));
+    }
+}
+
+#[derive(Resource)]
+struct ResizePipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    rescale_pipeline: CachedComputePipelineId,
+    clamp_agents_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for ResizePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let target = BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format: TRAIL_FORMAT,
+            view_dimension: TextureViewDimension::D2,
+        };
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("resize_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: target,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: target,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let shader = world.resource::<AssetServer>().load("shaders/resize.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from(entry_point)),
+                layout: Some(vec![layout.clone()]),
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+        let rescale_pipeline = queue("rescale");
+        let clamp_agents_pipeline = queue("clamp_agents");
+
+        ResizePipeline {
+            layout,
+            sampler,
+            rescale_pipeline,
+            clamp_agents_pipeline,
+        }
+    }
+}
+
+/// Only present in the render world in the frame of a resize
+#[derive(Resource)]
+struct ResizeBindGroup(BindGroup);
+
+fn queue_resize_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ResizePipeline>,
+    resize: Res<TrailResize>,
+    trail_images: Res<TrailImages>,
+    sim_meta: Res<SimMeta>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    commands.remove_resource::<ResizeBindGroup>();
+    let from = match &resize.from {
+        Some(from) => from,
+        None => return,
+    };
+    let [front, back] = &trail_images.images;
+    let (source, front, back) = match (
+        gpu_images.get(from),
+        gpu_images.get(front),
+        gpu_images.get(back),
+    ) {
+        (Some(source), Some(front), Some(back)) => (source, front, back),
+        _ => return,
+    };
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("resize_bind_group"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&source.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&pipeline.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&front.texture_view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(&back.texture_view),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: sim_meta.agents_buffer.as_entire_binding(),
+            },
+        ],
+    });
+    commands.insert_resource(ResizeBindGroup(bind_group));
+}
+
+struct ResizeNode;
+
+impl render_graph::Node for ResizeNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let bind_group = match world.get_resource::<ResizeBindGroup>() {
+            Some(bind_group) => &bind_group.0,
+            None => return Ok(()),
+        };
+        let pipeline = world.resource::<ResizePipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let settings = world.resource::<SimSettings>();
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_bind_group(0, bind_group, &[]);
+
+        // The trail stays cleared if the kernel has not compiled yet
+        if world.resource::<TrailResize>().rescale {
+            if let Some(rescale) = pipeline_cache.get_compute_pipeline(pipeline.rescale_pipeline) {
+                pass.set_pipeline(rescale);
+                pass.dispatch_workgroups(
+                    settings.width.div_ceil(WORKGROUP_SIZE),
+                    settings.height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+        }
+        if let Some(clamp_agents) =
+            pipeline_cache.get_compute_pipeline(pipeline.clamp_agents_pipeline)
+        {
+            pass.set_pipeline(clamp_agents);
+            pass.dispatch_workgroups(
+                world
+                    .resource::<SimMeta>()
+                    .num_agents
+                    .div_ceil(GAME_WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! Screenshots of the trail texture at simulation resolution, with the preset embedded in the PNG.
+//!
+//! The preset is written as RON into a `Preset` iTXt chunk, which is UTF-8 unlike tEXt, next to
+//! readable `Seed` and `Spawn Modes` chunks. Dropping such a PNG onto the window applies its
+//! preset, so a screenshot doubles as one.
+//!
+//! PNGs are written on their own thread, which reports back over a channel that
+//! [`poll_saved_screenshots`] turns into the status shown in the UI.
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    food_map::FoodMap, palette::Palette, preset::Preset, readback::CapturedFrame,
+    spawn_mask::SpawnMask, timeline::Timeline, RandArray, SimParams, SimSettings,
+};
+
+pub const SCREENSHOT_KEY: KeyCode = KeyCode::F12;
+
+const PRESET_KEYWORD: &str = "Preset";
+
+#[derive(Resource)]
+pub struct Screenshot {
+    /// Directory screenshots are saved to
+    pub output: String,
+    /// Set to take a screenshot, like pressing `SCREENSHOT_KEY`
+    pub requested: bool,
+    pub status: String,
+    /// Text chunks of the screenshot waiting for its frame, from the params it was taken with
+    pending: Option<Vec<(String, String)>>,
+    /// Status of each screenshot once its writer thread is done
+    saved_sender: Sender<String>,
+    saved_receiver: Receiver<String>,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        let (saved_sender, saved_receiver) = crossbeam_channel::unbounded();
+        Self {
+            output: "screenshots".to_string(),
+            requested: false,
+            status: String::new(),
+            pending: None,
+            saved_sender,
+            saved_receiver,
+        }
+    }
+}
+
+impl Screenshot {
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Saves `frame` on its own thread if a screenshot is waiting for one
+    pub fn save(&mut self, frame: &CapturedFrame) {
+        let text = match self.pending.take() {
+            Some(text) => text,
+            None => return,
+        };
+        let path = screenshot_path(&self.output);
+        self.status = format!("Saving {}", path.display());
+
+        let (width, height, data) = (frame.width, frame.height, frame.data.clone());
+        let sender = self.saved_sender.clone();
+        std::thread::spawn(move || {
+            let status = match save_png_with_text(&path, width, height, &data, &text) {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(err) => {
+                    error!("Failed to write {}: {}", path.display(), err);
+                    format!("Failed to write {}: {}", path.display(), err)
+                }
+            };
+            let _ = sender.send(status);
+        });
+    }
+}
+
+fn screenshot_path(output: &str) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    Path::new(output).join(format!("screenshot_{}.png", millis))
+}
+
+fn save_png_with_text(
+    path: &Path,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    text: &[(String, String)],
+) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let file = File::create(path).map_err(|err| err.to_string())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in text {
+        encoder
+            .add_itxt_chunk(keyword.clone(), text.clone())
+            .map_err(|err| err.to_string())?;
+    }
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer.write_image_data(data).map_err(|err| err.to_string())
+}
+
+/// The preset in the iTXt chunks of a PNG saved by [`Screenshot`]
+fn read_png_preset(path: &Path) -> Result<Preset, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let reader = png::Decoder::new(file)
+        .read_info()
+        .map_err(|err| err.to_string())?;
+    let chunk = reader
+        .info()
+        .utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == PRESET_KEYWORD)
+        .ok_or_else(|| "no preset in this image".to_string())?;
+    let text = chunk.get_text().map_err(|err| err.to_string())?;
+    Preset::from_ron(text.as_bytes()).map_err(|err| err.to_string())
+}
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Screenshot>()
+            .add_system(request_screenshot)
+            .add_system(poll_saved_screenshots)
+            .add_system(restore_dropped_screenshot);
+    }
+}
+
+/// Captures the params when the screenshot is taken, the frame itself arrives a little later
+#[allow(clippy::too_many_arguments)]
+pub fn request_screenshot(
+    mut screenshot: ResMut<Screenshot>,
+    keys: Res<Input<KeyCode>>,
+    sim_params: Res<SimParams>,
+    settings: Res<SimSettings>,
+    rand_array: Res<RandArray>,
+    spawn_mask: Res<SpawnMask>,
+    food_map: Res<FoodMap>,
+    palette: Res<Palette>,
+    timeline: Res<Timeline>,
+) {
+    if !keys.just_pressed(SCREENSHOT_KEY) && !screenshot.requested {
+        return;
+    }
+    screenshot.requested = false;
+
+    let preset = Preset::capture(
+        &sim_params,
+        &settings,
+        &rand_array,
+        &spawn_mask,
+        &food_map,
+        &palette,
+        &timeline,
+    );
+    let preset_text = match ron::ser::to_string_pretty(&preset, ron::ser::PrettyConfig::default()) {
+        Ok(text) => text,
+        Err(err) => {
+            screenshot.status = err.to_string();
+            return;
+        }
+    };
+    let spawn_modes = sim_params.species[..sim_params.species_count as usize]
+        .iter()
+        .map(|species| format!("{:?}", species.mode))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    screenshot.pending = Some(vec![
+        ("Software".to_string(), env!("CARGO_PKG_NAME").to_string()),
+        ("Seed".to_string(), settings.seed.to_string()),
+        ("Spawn Modes".to_string(), spawn_modes),
+        (PRESET_KEYWORD.to_string(), preset_text),
+    ]);
+}
+
+/// Shows how the last screenshot written went
+fn poll_saved_screenshots(mut screenshot: ResMut<Screenshot>) {
+    if let Some(status) = screenshot.saved_receiver.try_iter().last() {
+        screenshot.status = status;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_dropped_screenshot(
+    mut events: EventReader<FileDragAndDrop>,
+    mut screenshot: ResMut<Screenshot>,
+    mut sim_params: ResMut<SimParams>,
+    mut settings: ResMut<SimSettings>,
+    mut rand_array: ResMut<RandArray>,
+    mut spawn_mask: ResMut<SpawnMask>,
+    mut food_map: ResMut<FoodMap>,
+    mut palette: ResMut<Palette>,
+    mut timeline: ResMut<Timeline>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in events.iter() {
+        let path = match event {
+            FileDragAndDrop::DroppedFile { path_buf, .. } => path_buf,
+            _ => continue,
+        };
+        if !matches!(path.extension(), Some(extension) if extension.eq_ignore_ascii_case("png")) {
+            continue;
+        }
+
+        match read_png_preset(path) {
+            Ok(preset) => {
+                preset.apply(
+                    &mut sim_params,
+                    &mut settings,
+                    &mut rand_array,
+                    &mut palette,
+                    &mut timeline,
+                );
+                preset.load_images(&mut spawn_mask, &mut food_map, &asset_server);
+                screenshot.status = format!("Restored {}", path.display());
+            }
+            Err(err) => screenshot.status = format!("{}: {}", path.display(), err),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Spawn positions weighted by the luminance of an image, for `SimSpawnMode::ImageMask`.
+//!
+//! The image is turned into a cumulative distribution over its pixels, which `init` samples with a
+//! binary search. The mask is fitted into the simulation, keeping its aspect ratio.
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{Buffer, BufferInitDescriptor, BufferUsages, TextureFormat},
+        renderer::RenderDevice,
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::{SimMeta, SimParams, SimSettings, SimSpawnMode, SimState};
+
+/// The image `ImageMask` species spawn on, `path` is relative to `assets`
+#[derive(Resource, Default)]
+pub struct SpawnMask {
+    pub path: String,
+    pub handle: Option<Handle<Image>>,
+}
+
+impl SpawnMask {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &str) {
+        self.handle = Some(asset_server.load(path));
+        self.path = path.to_string();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaskTexel {
+    /// Luminance of this and all previous pixels, normalized to end at 1
+    pub cdf: f32,
+    pub luminance: f32,
+}
+
+/// The mask as the `init` kernel reads it, empty until an image has loaded.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct SpawnMaskData {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<MaskTexel>,
+}
+
+impl SpawnMaskData {
+    /// Luminance is weighted by alpha, so transparent parts of a logo spawn nothing.
+    ///
+    /// Returns `None` for formats Bevy can not convert and for masks without any bright pixel.
+    pub fn from_image(image: &Image) -> Option<Self> {
+        let luma_alpha = image.convert(TextureFormat::Rg8Unorm)?;
+        let size = luma_alpha.texture_descriptor.size;
+
+        let mut total = 0.0f64;
+        let mut texels: Vec<MaskTexel> = luma_alpha
+            .data
+            .chunks_exact(2)
+            .map(|pixel| {
+                let luminance = pixel[0] as f32 / 255.0 * pixel[1] as f32 / 255.0;
+                total += luminance as f64;
+                MaskTexel {
+                    cdf: total as f32,
+                    luminance,
+                }
+            })
+            .collect();
+        if total <= 0.0 {
+            return None;
+        }
+        for texel in texels.iter_mut() {
+            texel.cdf = (texel.cdf as f64 / total) as f32;
+        }
+
+        Some(Self {
+            width: size.width,
+            height: size.height,
+            texels,
+        })
+    }
+
+    /// The size of the mask followed by its texels, matching `SpawnMask` in the shader
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + std::mem::size_of_val(self.texels.as_slice()));
+        bytes.extend_from_slice(bytemuck::cast_slice(&[self.width, self.height]));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.texels));
+        // The runtime sized array needs at least one element to bind
+        if self.texels.is_empty() {
+            bytes.extend_from_slice(bytemuck::bytes_of(&MaskTexel::default()));
+        }
+        bytes
+    }
+}
+
+pub fn create_spawn_mask_buffer(render_device: &RenderDevice, data: &SpawnMaskData) -> Buffer {
+    render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("Spawn mask buffer"),
+        contents: &data.to_bytes(),
+        usage: BufferUsages::STORAGE,
+    })
+}
+
+pub struct SpawnMaskPlugin;
+
+impl Plugin for SpawnMaskPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnMask>()
+            .init_resource::<SpawnMaskData>()
+            .add_plugin(ExtractResourcePlugin::<SpawnMaskData>::default())
+            .add_system(update_spawn_mask);
+
+        app.sub_app_mut(RenderApp)
+            .add_system_to_stage(RenderStage::Prepare, prepare_spawn_mask);
+    }
+}
+
+/// Rebuilds the mask data once the image has (re)loaded, and respawns the species that use it
+fn update_spawn_mask(
+    mut events: EventReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+    spawn_mask: Res<SpawnMask>,
+    mut data: ResMut<SpawnMaskData>,
+    sim_params: Res<SimParams>,
+    mut settings: ResMut<SimSettings>,
+) {
+    let handle = match &spawn_mask.handle {
+        Some(handle) => handle,
+        None => return,
+    };
+    let mut loaded = spawn_mask.is_changed();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: image } | AssetEvent::Modified { handle: image } => {
+                loaded |= image == handle
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    if !loaded {
+        return;
+    }
+    let image = match images.get(handle) {
+        Some(image) => image,
+        None => return,
+    };
+    match SpawnMaskData::from_image(image) {
+        Some(mask) => *data = mask,
+        None => {
+            warn!("{} has no bright pixels to spawn on", spawn_mask.path);
+            return;
+        }
+    }
+
+    if sim_params.species[..sim_params.species_count as usize]
+        .iter()
+        .any(|species| species.mode == SimSpawnMode::ImageMask)
+    {
+        settings.state = SimState::Initialize;
+    }
+}
+
+fn prepare_spawn_mask(
+    data: Res<SpawnMaskData>,
+    mut sim_meta: ResMut<SimMeta>,
+    render_device: Res<RenderDevice>,
+) {
+    if data.is_changed() {
+        sim_meta.spawn_mask_buffer = create_spawn_mask_buffer(&render_device, &data);
+    }
+}
@@ -0,0 +1,350 @@
+//! Keyframed automation of `SimParams` fields, saved with presets.
+//!
+//! The timeline plays in real time without the time scale, and advances exactly one frame per
+//! frame while recording, so recordings follow it exactly. A track sets its field to the value
+//! of its curve whenever that value changes, so the field can still be edited and keyed while the
+//! curve holds still. Audio modulation adds on top when the params are exported.
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Button, Checkbox, Color32, ComboBox, DragValue, Sense, Slider, Stroke},
+    EguiContext,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    param_target::ParamTarget, update_params, EguiState, SimParams, SimSettings, SimState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Linear,
+    /// Holds the value until the next keyframe
+    Step,
+    /// Eases in and out of both keyframes
+    Ease,
+}
+
+impl Interpolation {
+    const ALL: [Self; 3] = [Self::Linear, Self::Step, Self::Ease];
+
+    /// Maps the progress between two keyframes onto their values
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::Step => 0.0,
+            Self::Ease => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the timeline
+    pub time: f32,
+    pub value: f32,
+    /// The curve towards the next keyframe
+    pub interpolation: Interpolation,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub target: ParamTarget,
+    /// Sorted by time
+    pub keyframes: Vec<Keyframe>,
+    /// The value last written to the target, `None` until the next frame writes it again
+    #[serde(skip)]
+    written: Option<f32>,
+}
+
+impl Track {
+    fn new(target: ParamTarget) -> Self {
+        Self {
+            target,
+            keyframes: Vec::new(),
+            written: None,
+        }
+    }
+
+    /// Holds the first and last values before and after the keyframes
+    pub fn value(&self, time: f32) -> Option<f32> {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time);
+        match next {
+            None => self.keyframes.last().map(|keyframe| keyframe.value),
+            Some(0) => Some(self.keyframes[0].value),
+            Some(index) => {
+                let (from, to) = (&self.keyframes[index - 1], &self.keyframes[index]);
+                let t = (time - from.time) / (to.time - from.time);
+                Some(from.value + (to.value - from.value) * from.interpolation.apply(t))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+pub struct Timeline {
+    pub tracks: Vec<Track>,
+    /// Seconds
+    pub length: f32,
+    pub looping: bool,
+    #[serde(skip)]
+    pub playing: bool,
+    /// Playback position in seconds
+    #[serde(skip)]
+    pub time: f32,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            tracks: Vec::new(),
+            length: 30.0,
+            looping: true,
+            playing: false,
+            time: 0.0,
+        }
+    }
+}
+
+impl Timeline {
+    /// Moves the playback position, tracks set their fields on the next frame
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.length);
+        for track in self.tracks.iter_mut() {
+            track.written = None;
+        }
+    }
+
+    /// Takes the tracks of a preset's timeline, playback carries on where it is
+    pub fn load(&mut self, other: &Timeline) {
+        self.tracks = other.tracks.clone();
+        self.length = other.length;
+        self.looping = other.looping;
+        self.seek(self.time);
+    }
+
+    /// Whether the saved parts differ
+    pub fn differs(&self, other: &Timeline) -> bool {
+        let tracks_differ = self.tracks.len() != other.tracks.len()
+            || self
+                .tracks
+                .iter()
+                .zip(other.tracks.iter())
+                .any(|(a, b)| a.target != b.target || a.keyframes != b.keyframes);
+        tracks_differ || self.length != other.length || self.looping != other.looping
+    }
+}
+
+pub struct TimelinePlugin;
+
+impl Plugin for TimelinePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Timeline>()
+            .add_system(play_timeline.after(update_params));
+    }
+}
+
+/// Advances the playback position and writes the tracks to their fields
+fn play_timeline(
+    mut timeline: ResMut<Timeline>,
+    mut sim_params: ResMut<SimParams>,
+    settings: Res<SimSettings>,
+    time: Res<Time>,
+) {
+    let timeline = timeline.as_mut();
+    if timeline.playing {
+        match settings.state {
+            SimState::Initialize => timeline.seek(0.0),
+            SimState::Playing => timeline.time += settings.frame_delta(time.delta_seconds()),
+            SimState::Paused => {}
+        }
+        if timeline.time >= timeline.length {
+            if timeline.looping && timeline.length > 0.0 {
+                timeline.seek(timeline.time % timeline.length);
+            } else {
+                timeline.seek(timeline.length);
+                timeline.playing = false;
+            }
+        }
+    }
+
+    let mut changed = false;
+    for track in timeline.tracks.iter_mut() {
+        let value = match track.value(timeline.time) {
+            Some(value) => value,
+            None => continue,
+        };
+        if track.written == Some(value) {
+            continue;
+        }
+        if let Some(field) = track.target.field(sim_params.bypass_change_detection()) {
+            *field = value;
+            changed = true;
+        }
+        track.written = Some(value);
+    }
+    if changed {
+        sim_params.set_changed();
+    }
+}
+
+pub fn ui_timeline(
+    mut egui_context: ResMut<EguiContext>,
+    mut timeline: ResMut<Timeline>,
+    sim_params: Res<SimParams>,
+    egui_state: Res<EguiState>,
+) {
+    if !egui_state.all_visible {
+        return;
+    }
+
+    let mut edited = timeline.clone();
+    let mut seek = None;
+    egui::Window::new("Timeline").show(egui_context.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            let label = if edited.playing { "Pause" } else { "Play" };
+            if ui.add(Button::new(label)).clicked() {
+                edited.playing = !edited.playing;
+                if edited.playing {
+                    seek = Some(if edited.time >= edited.length {
+                        0.0
+                    } else {
+                        edited.time
+                    });
+                }
+            }
+            if ui.add(Button::new("Stop")).clicked() {
+                edited.playing = false;
+                seek = Some(0.0);
+            }
+            ui.add(Checkbox::new(&mut edited.looping, "Loop"));
+            ui.add(
+                DragValue::new(&mut edited.length)
+                    .clamp_range(1.0..=3600.0)
+                    .prefix("length: ")
+                    .suffix("s"),
+            );
+        });
+        let mut time = edited.time;
+        if ui
+            .add(Slider::new(&mut time, 0.0..=edited.length).suffix("s"))
+            .changed()
+        {
+            seek = Some(time);
+        }
+
+        let targets = ParamTarget::all(sim_params.species_count as usize);
+        let length = edited.length;
+        let mut removed = None;
+        for (track_index, track) in edited.tracks.iter_mut().enumerate() {
+            ui.push_id(track_index, |ui| {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ComboBox::from_id_source("target")
+                        .selected_text(track.target.label())
+                        .show_ui(ui, |ui| {
+                            for target in targets.iter() {
+                                ui.selectable_value(&mut track.target, *target, target.label());
+                            }
+                        });
+                    // Keys the value the field has right now
+                    if ui.add(Button::new("Key")).clicked() {
+                        let mut params = *sim_params;
+                        if let Some(value) = track.target.field(&mut params).map(|field| *field) {
+                            track
+                                .keyframes
+                                .retain(|keyframe| keyframe.time != edited.time);
+                            track.keyframes.push(Keyframe {
+                                time: edited.time,
+                                value,
+                                interpolation: Interpolation::Linear,
+                            });
+                        }
+                    }
+                    if ui.add(Button::new("x")).clicked() {
+                        removed = Some(track_index);
+                    }
+                });
+
+                // Keyframes along the timeline, dragging one moves it in time
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(ui.available_width(), 16.0), Sense::hover());
+                let x = |time: f32| rect.left() + rect.width() * time / length;
+                ui.painter().rect_filled(rect, 2.0, Color32::from_gray(40));
+                for (index, keyframe) in track.keyframes.iter_mut().enumerate() {
+                    let center = egui::pos2(x(keyframe.time), rect.center().y);
+                    let response = ui.interact(
+                        egui::Rect::from_center_size(center, egui::vec2(10.0, 16.0)),
+                        ui.id().with(index),
+                        Sense::drag(),
+                    );
+                    if response.dragged() {
+                        keyframe.time = (keyframe.time
+                            + response.drag_delta().x / rect.width() * length)
+                            .clamp(0.0, length);
+                    }
+                    ui.painter().circle_filled(center, 4.0, Color32::WHITE);
+                }
+                ui.painter().vline(
+                    x(edited.time),
+                    rect.y_range(),
+                    Stroke::new(1.0, Color32::RED),
+                );
+
+                let mut remove = None;
+                for (index, keyframe) in track.keyframes.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            DragValue::new(&mut keyframe.time)
+                                .speed(0.05)
+                                .clamp_range(0.0..=length)
+                                .suffix("s"),
+                        );
+                        ui.add(DragValue::new(&mut keyframe.value).speed(0.01));
+                        ComboBox::from_id_source(index)
+                            .selected_text(format!("{:?}", keyframe.interpolation))
+                            .show_ui(ui, |ui| {
+                                for interpolation in Interpolation::ALL {
+                                    ui.selectable_value(
+                                        &mut keyframe.interpolation,
+                                        interpolation,
+                                        format!("{:?}", interpolation),
+                                    );
+                                }
+                            });
+                        if ui.add(Button::new("-")).clicked() {
+                            remove = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = remove {
+                    track.keyframes.remove(index);
+                }
+            });
+            // Sorting mid-drag would hand the drag to another keyframe
+            if !ui.ctx().is_using_pointer() {
+                track.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+            }
+        }
+        if let Some(index) = removed {
+            edited.tracks.remove(index);
+        }
+        if ui.add(Button::new("Add Track")).clicked() {
+            edited.tracks.push(Track::new(ParamTarget::DecayRate));
+        }
+    });
+
+    if let Some(time) = seek {
+        edited.seek(time);
+    }
+    if edited != *timeline {
+        // Edited curves write their fields again, they may have jumped
+        if edited.differs(&timeline) {
+            let time = edited.time;
+            edited.seek(time);
+        }
+        *timeline = edited;
+    }
+}